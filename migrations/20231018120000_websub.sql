@@ -0,0 +1,10 @@
+-- WebSub (PubSubHubbub) push subscriptions, at most one per channel
+CREATE TABLE websub_subscription (
+    channel_id uuid primary key references channel(id) ON DELETE CASCADE not null,
+    hub text not null,
+    topic text not null,
+    secret text not null,
+    verified boolean not null DEFAULT false,
+    lease_expires_at timestamptz,
+    updated_at timestamptz not null DEFAULT now()
+);
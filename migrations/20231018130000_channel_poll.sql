@@ -0,0 +1,12 @@
+-- Per-channel polling schedule, rows are created after a channel's first poll
+CREATE TABLE channel_poll (
+    channel_id uuid primary key references channel(id) ON DELETE CASCADE not null,
+    next_poll_at timestamptz not null,
+    last_polled_at timestamptz not null,
+    last_success_at timestamptz,
+    failure_count integer not null DEFAULT 0,
+    poll_interval integer not null, -- seconds
+    refresh_hint integer -- seconds, advertised through <ttl> or sy:updatePeriod
+);
+
+CREATE INDEX channel_poll_next_poll_at_idx ON channel_poll(next_poll_at);
@@ -0,0 +1,5 @@
+-- Outcome of the most recent poll, surfaced so users can see why a channel stopped updating
+ALTER TABLE channel_poll
+ADD COLUMN last_status text,
+ADD COLUMN last_error text,
+ADD COLUMN last_http_status integer;
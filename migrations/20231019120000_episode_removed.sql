@@ -0,0 +1,2 @@
+-- episodes that disappear from their feed are kept (history, downloads) but hidden
+ALTER TABLE episode ADD COLUMN removed_at timestamptz;
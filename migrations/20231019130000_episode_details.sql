@@ -0,0 +1,10 @@
+-- enclosure details and iTunes episode tags
+ALTER TABLE episode
+ADD COLUMN audio_type text,
+ADD COLUMN audio_size bigint,
+ADD COLUMN duration integer, -- seconds
+ADD COLUMN season integer,
+ADD COLUMN episode_number integer,
+ADD COLUMN episode_type text, -- full, trailer or bonus
+ADD COLUMN explicit boolean,
+ADD COLUMN image text;
@@ -0,0 +1,38 @@
+-- Podcasting 2.0 namespace tags (https://podcastindex.org/namespace/1.0)
+ALTER TABLE channel
+ADD COLUMN podcast_guid text,
+ADD COLUMN locked boolean;
+
+CREATE TABLE channel_funding (
+    channel_id uuid references channel(id) ON DELETE CASCADE not null,
+    url text not null,
+    message text,
+    CONSTRAINT channel_funding_pk PRIMARY KEY(channel_id, url)
+);
+
+CREATE TABLE episode_transcript (
+    episode_id uuid references episode(id) ON DELETE CASCADE not null,
+    url text not null,
+    mime_type text not null,
+    language text,
+    rel text,
+    CONSTRAINT episode_transcript_pk PRIMARY KEY(episode_id, url)
+);
+
+CREATE TABLE episode_chapters (
+    episode_id uuid primary key references episode(id) ON DELETE CASCADE not null,
+    url text not null,
+    mime_type text not null
+);
+
+CREATE TABLE podcast_person (
+    channel_id uuid references channel(id) ON DELETE CASCADE not null,
+    episode_id uuid references episode(id) ON DELETE CASCADE, -- null when credited for the whole channel
+    name text not null,
+    role text not null,
+    person_group text not null,
+    image text,
+    href text
+);
+
+CREATE INDEX podcast_person_channel_idx ON podcast_person(channel_id, episode_id);
@@ -0,0 +1,23 @@
+-- Full-text search documents, weighted so titles rank above tags, then show notes.
+-- The 'simple' configuration skips stemming since feeds come in every language.
+-- Expression indexes keep themselves current as episodes and channels are written.
+CREATE FUNCTION episode_document(title text, tags text, description text, content text)
+RETURNS tsvector LANGUAGE sql IMMUTABLE AS $$
+    SELECT setweight(to_tsvector('simple', coalesce(title, '')), 'A')
+        || setweight(to_tsvector('simple', replace(coalesce(tags, ''), ',', ' ')), 'B')
+        || setweight(to_tsvector('simple', coalesce(description, '')), 'C')
+        || setweight(to_tsvector('simple', coalesce(content, '')), 'D')
+$$;
+
+CREATE FUNCTION channel_document(title text, author text, description text)
+RETURNS tsvector LANGUAGE sql IMMUTABLE AS $$
+    SELECT setweight(to_tsvector('simple', coalesce(title, '')), 'A')
+        || setweight(to_tsvector('simple', coalesce(author, '')), 'B')
+        || setweight(to_tsvector('simple', coalesce(description, '')), 'C')
+$$;
+
+CREATE INDEX episode_search_idx ON episode
+USING GIN (episode_document(title, tags, description, content));
+
+CREATE INDEX channel_search_idx ON channel
+USING GIN (channel_document(title, author, description));
@@ -0,0 +1,17 @@
+-- Artwork downloaded from publishers, resized variants live under IMAGE_STORAGE_PATH/<id>/
+CREATE TABLE image (
+    id uuid primary key, -- derived from the source url
+    source_url text unique not null,
+    content_hash text not null, -- sha256 of the downloaded file, variants are regenerated when it changes
+    mime_type text not null,
+    width int not null,
+    height int not null,
+    fetched_at timestamptz not null default now(),
+    updated_at timestamptz not null default now()
+);
+
+ALTER TABLE channel
+ADD COLUMN image_id uuid references image(id) ON DELETE SET NULL;
+
+ALTER TABLE episode
+ADD COLUMN image_id uuid references image(id) ON DELETE SET NULL;
@@ -0,0 +1,28 @@
+-- Episode files stored on the server, shared by every user that asked for them
+ALTER TABLE user_subscriptions
+ADD COLUMN download_latest int; -- keep this many of the newest episodes downloaded, null when off
+
+CREATE TABLE download (
+    episode_id uuid primary key references episode(id) ON DELETE CASCADE not null,
+    status text not null default 'pending', -- pending, downloading, complete, failed or evicted
+    size bigint not null default 0, -- bytes on disk, including a partial file
+    total_size bigint, -- as announced by the host
+    mime_type text,
+    sha256 text,
+    validator text, -- ETag or Last-Modified of the partial file, sent as If-Range when resuming
+    attempts int not null default 0,
+    error text,
+    retry_at timestamptz not null default now(), -- also the lease of a running download
+    created_at timestamptz not null default now(),
+    completed_at timestamptz,
+    last_accessed_at timestamptz not null default now()
+);
+
+CREATE TABLE user_download (
+    user_id uuid references account(id) ON DELETE CASCADE not null,
+    episode_id uuid references download(episode_id) ON DELETE CASCADE not null,
+    automatic boolean not null default false, -- added by a subscription rule, dropped once the rule no longer matches
+    evicted boolean not null default false, -- pushed out by a quota, kept so rules don't fetch the file again
+    created_at timestamptz not null default now(),
+    CONSTRAINT user_download_pk PRIMARY KEY(user_id, episode_id)
+);
@@ -0,0 +1,12 @@
+-- Where each user left off in every episode they started, whichever device they played it on
+CREATE TABLE playback_position (
+    user_id uuid references account(id) ON DELETE CASCADE not null,
+    episode_id uuid references episode(id) ON DELETE CASCADE not null,
+    position double precision not null, -- seconds
+    duration double precision, -- seconds, as reported by the player
+    device text,
+    updated_at timestamptz not null default now(),
+    CONSTRAINT playback_position_pk PRIMARY KEY(user_id, episode_id)
+);
+
+CREATE INDEX playback_position_recent_idx ON playback_position(user_id, updated_at DESC);
@@ -0,0 +1,13 @@
+-- Up Next, ordered by a fractional sort key so an item moves without renumbering the rest
+CREATE TABLE queue_item (
+    user_id uuid references account(id) ON DELETE CASCADE not null,
+    episode_id uuid references episode(id) ON DELETE CASCADE not null,
+    sort_key double precision not null,
+    added_at timestamptz not null default now(),
+    CONSTRAINT queue_item_pk PRIMARY KEY(user_id, episode_id)
+);
+
+CREATE INDEX queue_item_order_idx ON queue_item(user_id, sort_key);
+
+ALTER TABLE user_subscriptions
+ADD COLUMN download_queued boolean not null default false; -- download episodes of the channel while they are queued
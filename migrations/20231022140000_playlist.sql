@@ -0,0 +1,43 @@
+-- Named, ordered collections of episodes, optionally shared with other accounts
+CREATE TABLE playlist (
+    id uuid primary key not null,
+    owner_id uuid references account(id) ON DELETE CASCADE not null,
+    name text not null,
+    description text,
+    created_at timestamptz not null default now(),
+    updated_at timestamptz not null default now()
+);
+
+CREATE INDEX playlist_owner_idx ON playlist(owner_id);
+
+CREATE TABLE playlist_item (
+    playlist_id uuid references playlist(id) ON DELETE CASCADE not null,
+    episode_id uuid references episode(id) ON DELETE CASCADE not null,
+    sort_key double precision not null, -- fractional, like queue_item
+    added_by uuid references account(id) ON DELETE SET NULL,
+    added_at timestamptz not null default now(),
+    CONSTRAINT playlist_item_pk PRIMARY KEY(playlist_id, episode_id)
+);
+
+CREATE TABLE playlist_member (
+    playlist_id uuid references playlist(id) ON DELETE CASCADE not null,
+    user_id uuid references account(id) ON DELETE CASCADE not null,
+    role text not null, -- viewer or editor, the owner isn't a member
+    created_at timestamptz not null default now(),
+    CONSTRAINT playlist_member_pk PRIMARY KEY(playlist_id, user_id)
+);
+
+CREATE INDEX playlist_member_user_idx ON playlist_member(user_id);
+
+-- Who changed what, kept when the episode or account is gone
+CREATE TABLE playlist_edit (
+    id bigserial primary key,
+    playlist_id uuid references playlist(id) ON DELETE CASCADE not null,
+    user_id uuid references account(id) ON DELETE SET NULL,
+    action text not null, -- create, update, add, remove, move, share or unshare
+    episode_id uuid,
+    member_id uuid,
+    created_at timestamptz not null default now()
+);
+
+CREATE INDEX playlist_edit_playlist_idx ON playlist_edit(playlist_id, created_at DESC);
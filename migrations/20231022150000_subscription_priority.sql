@@ -0,0 +1,3 @@
+-- How much a subscription matters to the user, from -2 (lowest) to 2 (highest)
+ALTER TABLE user_subscriptions
+ADD COLUMN priority int not null default 0 CHECK (priority BETWEEN -2 AND 2);
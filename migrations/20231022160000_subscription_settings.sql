@@ -0,0 +1,7 @@
+-- How the user wants each subscription handled, alongside priority and the download rules
+ALTER TABLE user_subscriptions
+ADD COLUMN playback_speed double precision not null default 1 CHECK (playback_speed BETWEEN 0.25 AND 4),
+ADD COLUMN skip_intro int not null default 0 CHECK (skip_intro >= 0), -- seconds
+ADD COLUMN skip_outro int not null default 0 CHECK (skip_outro >= 0), -- seconds
+ADD COLUMN auto_queue boolean not null default false, -- new episodes go to the end of the queue
+ADD COLUMN notifications boolean not null default true;
@@ -0,0 +1,23 @@
+-- Episodes a user starred
+CREATE TABLE episode_star (
+    user_id uuid references account(id) ON DELETE CASCADE not null,
+    episode_id uuid references episode(id) ON DELETE CASCADE not null,
+    created_at timestamptz not null default now(),
+    CONSTRAINT episode_star_pk PRIMARY KEY(user_id, episode_id)
+);
+
+CREATE INDEX episode_star_user_idx ON episode_star(user_id, created_at DESC);
+
+-- A moment in an episode the user wants to come back to, with an optional note
+CREATE TABLE bookmark (
+    id uuid primary key,
+    user_id uuid references account(id) ON DELETE CASCADE not null,
+    episode_id uuid references episode(id) ON DELETE CASCADE not null,
+    position double precision not null CHECK (position >= 0), -- seconds into the episode
+    note text,
+    created_at timestamptz not null default now(),
+    updated_at timestamptz not null default now()
+);
+
+CREATE INDEX bookmark_user_idx ON bookmark(user_id, created_at DESC);
+CREATE INDEX bookmark_episode_idx ON bookmark(user_id, episode_id, position);
@@ -0,0 +1,13 @@
+-- History entries follow partial plays as well, and replays update the existing entry
+ALTER TABLE user_watch_history
+ADD COLUMN first_listened_at timestamptz not null default now(),
+ADD COLUMN last_listened_at timestamptz not null default now(),
+ADD COLUMN seconds_listened double precision not null default 0,
+ADD COLUMN completion double precision not null default 0 CHECK (completion BETWEEN 0 AND 100), -- furthest point reached, in percent
+ADD COLUMN state text not null default 'in_progress' CHECK (state IN ('played', 'in_progress', 'abandoned')),
+ADD COLUMN play_count int not null default 0; -- times the episode was finished
+
+-- until now, an entry meant the episode was played
+UPDATE user_watch_history SET state = 'played', completion = 100, play_count = 1;
+
+CREATE INDEX user_watch_history_recent_idx ON user_watch_history(user_id, last_listened_at DESC);
@@ -0,0 +1,14 @@
+-- Seconds listened per user, episode and UTC hour, kept up to date with every position report
+-- so listening stats never have to go through raw events
+CREATE TABLE listening_hour (
+    user_id uuid references account(id) ON DELETE CASCADE not null,
+    episode_id uuid references episode(id) ON DELETE CASCADE not null,
+    hour timestamptz not null,
+    seconds double precision not null,
+    CONSTRAINT listening_hour_pk PRIMARY KEY(user_id, hour, episode_id)
+);
+
+-- history from before this only knows the last time an episode was listened to
+INSERT INTO listening_hour(user_id, episode_id, hour, seconds)
+SELECT user_id, episode_id, date_trunc('hour', last_listened_at, 'UTC'), seconds_listened
+FROM user_watch_history WHERE seconds_listened > 0;
@@ -0,0 +1,2 @@
+-- Hubs that refused a subscription keep their row, so polls don't keep asking them again
+ALTER TABLE websub_subscription ADD COLUMN denied boolean not null DEFAULT false;
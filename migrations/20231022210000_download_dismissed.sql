@@ -0,0 +1,3 @@
+ALTER TABLE user_download
+    -- deleted by the user while a rule still matches, kept so the rule doesn't add it back
+    ADD COLUMN dismissed boolean not null default false;
@@ -0,0 +1,7 @@
+-- Artwork that could not be downloaded or decoded, retried after a growing backoff instead of on every poll
+CREATE TABLE image_failure (
+    source_url text primary key,
+    error text not null,
+    failure_count int not null default 1,
+    fetched_at timestamptz not null default now()
+);
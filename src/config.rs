@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
@@ -6,6 +7,8 @@ use envconfig::Envconfig;
 use redis::aio::ConnectionManager;
 use sqlx::{postgres::PgPoolOptions, PgPool, Pool};
 
+use crate::core::limiter::FetchLimiter;
+
 #[derive(Envconfig, Debug, Clone)]
 pub struct Config {
     #[envconfig(from = "API_HOST", default = "0.0.0.0")]
@@ -21,6 +24,41 @@ pub struct Config {
     pub redis_url: String,
     #[envconfig(from = "IMAGE_STORAGE_PATH", default = "/srv/librepod")]
     pub image_storage_path: String,
+    #[envconfig(from = "DOWNLOAD_STORAGE_PATH", default = "/srv/librepod/downloads")]
+    pub download_storage_path: String,
+    // disk space for downloaded episodes, least recently used files are evicted beyond it
+    #[envconfig(from = "DOWNLOAD_QUOTA_MB", default = "51200")]
+    pub download_quota_mb: u64,
+    #[envconfig(from = "DOWNLOAD_USER_QUOTA_MB", default = "10240")]
+    pub download_user_quota_mb: u64,
+    #[envconfig(from = "DOWNLOAD_CONCURRENCY", default = "2")]
+    pub download_concurrency: usize,
+    // externally reachable address, used for WebSub callbacks
+    #[envconfig(from = "PUBLIC_URL", default = "http://localhost:3000")]
+    pub public_url: String,
+    #[envconfig(from = "FETCH_CONCURRENCY", default = "16")]
+    pub fetch_concurrency: usize,
+    #[envconfig(from = "FETCH_HOST_CONCURRENCY", default = "2")]
+    pub fetch_host_concurrency: usize,
+    // mark episodes that vanished from a fetched feed as removed, off keeps them listed as is
+    #[envconfig(from = "MARK_REMOVED_EPISODES", default = "true")]
+    pub mark_removed_episodes: bool,
+    // PodcastIndex-compatible directory, searching is disabled without credentials
+    #[envconfig(
+        from = "PODCASTINDEX_URL",
+        default = "https://api.podcastindex.org/api/1.0"
+    )]
+    pub podcastindex_url: String,
+    #[envconfig(from = "PODCASTINDEX_KEY")]
+    pub podcastindex_key: Option<String>,
+    #[envconfig(from = "PODCASTINDEX_SECRET")]
+    pub podcastindex_secret: Option<String>,
+    // resolves Apple Podcasts share links to feeds
+    #[envconfig(
+        from = "ITUNES_LOOKUP_URL",
+        default = "https://itunes.apple.com/lookup"
+    )]
+    pub itunes_lookup_url: String,
 }
 
 #[derive(Clone)]
@@ -28,6 +66,7 @@ pub struct AppContext {
     pub redis_manager: ConnectionManager,
     pub pool: PgPool,
     pub config: Config,
+    pub fetch_limiter: Arc<FetchLimiter>,
 }
 
 impl Config {
@@ -70,9 +109,14 @@ pub async fn init_context() -> AppContext {
     let redis_manager = create_redis_manager(&config.redis_url)
         .await
         .expect("Failed to connect to redis");
+    let fetch_limiter = Arc::new(FetchLimiter::new(
+        config.fetch_concurrency,
+        config.fetch_host_concurrency,
+    ));
     AppContext {
         pool,
         redis_manager,
         config,
+        fetch_limiter,
     }
 }
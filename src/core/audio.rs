@@ -0,0 +1,240 @@
+// Streams episode audio through the server, so listeners never connect to podcast hosts themselves
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures::{future, stream::BoxStream, StreamExt, TryStreamExt};
+use http::{header, HeaderMap, HeaderValue, StatusCode};
+
+// headers describing the audio itself, anything identifying the host's infrastructure is dropped
+const PASSTHROUGH_HEADERS: [header::HeaderName; 6] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
+/// A single range of a `Range: bytes=` header, bounds are inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=start-` or `bytes=start-end`
+    From { start: u64, end: Option<u64> },
+    /// `bytes=-length`, the last `length` bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// `None` for malformed headers and for multiple ranges, which are served as the full file
+    pub fn parse(header: &str) -> Option<Self> {
+        let (start, end) = header.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            return end.parse().ok().map(Self::Suffix);
+        }
+        let start = start.parse().ok()?;
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(end.parse().ok()?)
+        };
+        if end.is_some_and(|end| end < start) {
+            return None;
+        }
+        Some(Self::From { start, end })
+    }
+
+    /// First and last byte within a file of `total` bytes, `None` when the range can't be satisfied
+    pub fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        let (start, end) = match *self {
+            Self::From { start, end } => {
+                (start, end.unwrap_or(u64::MAX).min(total.checked_sub(1)?))
+            }
+            Self::Suffix(0) => return None,
+            Self::Suffix(length) => (total.saturating_sub(length), total.checked_sub(1)?),
+        };
+        (start <= end).then_some((start, end))
+    }
+}
+
+pub struct AudioStream {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BoxStream<'static, reqwest::Result<Bytes>>,
+}
+
+/// Requests the enclosure on behalf of a client, forwarding its `Range` and `If-Range` headers.
+/// Redirects (usually analytics prefixes) are followed here rather than by the client.
+/// Hosts that ignore ranges get them applied to the full response instead.
+pub async fn stream_audio(
+    url: &str,
+    range: Option<&HeaderValue>,
+    if_range: Option<&HeaderValue>,
+) -> Result<AudioStream> {
+    // no overall timeout, a stream lasts as long as the client keeps listening,
+    // and no compression, it would shift the byte offsets of ranges
+    let client = reqwest::Client::builder()
+        .user_agent(concat!("librepod/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(30))
+        .no_gzip()
+        .no_brotli()
+        .no_deflate()
+        .build()?;
+    let mut request = client.get(url);
+    if let Some(range) = range {
+        request = request.header(header::RANGE, range);
+    }
+    if let Some(if_range) = if_range {
+        request = request.header(header::IF_RANGE, if_range);
+    }
+    let response = request.send().await?;
+
+    let status = response.status();
+    if !matches!(
+        status,
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT | StatusCode::RANGE_NOT_SATISFIABLE
+    ) {
+        return Err(anyhow!("audio host responded with {status}"));
+    }
+    let mut headers = HeaderMap::new();
+    for name in PASSTHROUGH_HEADERS {
+        if let Some(value) = response.headers().get(&name) {
+            headers.insert(name, value.clone());
+        }
+    }
+    let total = response.content_length();
+    let audio = AudioStream {
+        status,
+        headers,
+        body: response.bytes_stream().boxed(),
+    };
+
+    let requested = range
+        .and_then(|range| range.to_str().ok())
+        .and_then(ByteRange::parse);
+    match (status, requested, total) {
+        (StatusCode::OK, Some(requested), Some(total))
+            if validator_matches(&audio.headers, if_range) =>
+        {
+            Ok(apply_range(audio, requested, total))
+        }
+        _ => Ok(audio),
+    }
+}
+
+/// Whether `If-Range` allows serving a range, compared the way RFC 9110 asks: strong ETags or exact dates
+fn validator_matches(headers: &HeaderMap, if_range: Option<&HeaderValue>) -> bool {
+    let Some(if_range) = if_range else {
+        return true;
+    };
+    let is_strong = !if_range.as_bytes().starts_with(b"W/");
+    [header::ETAG, header::LAST_MODIFIED]
+        .iter()
+        .any(|name| is_strong && headers.get(name) == Some(if_range))
+}
+
+/// Cuts the range out of a full response, for hosts without range support
+fn apply_range(mut audio: AudioStream, requested: ByteRange, total: u64) -> AudioStream {
+    audio
+        .headers
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let Some((start, end)) = requested.resolve(total) else {
+        audio.status = StatusCode::RANGE_NOT_SATISFIABLE;
+        audio.headers.remove(header::CONTENT_TYPE);
+        audio.headers.insert(header::CONTENT_LENGTH, 0.into());
+        if let Ok(value) = HeaderValue::from_str(&format!("bytes */{total}")) {
+            audio.headers.insert(header::CONTENT_RANGE, value);
+        }
+        audio.body = futures::stream::empty().boxed();
+        return audio;
+    };
+
+    let length = end - start + 1;
+    audio.status = StatusCode::PARTIAL_CONTENT;
+    audio.headers.insert(header::CONTENT_LENGTH, length.into());
+    if let Ok(value) = HeaderValue::from_str(&format!("bytes {start}-{end}/{total}")) {
+        audio.headers.insert(header::CONTENT_RANGE, value);
+    }
+    audio.body = audio
+        .body
+        .scan((start, length), |(skip, take), chunk| {
+            // stop reading from the host once the range is complete
+            if *take == 0 {
+                return future::ready(None);
+            }
+            let chunk = chunk.map(|mut bytes| {
+                let skipped = (*skip).min(bytes.len() as u64);
+                bytes = bytes.slice(skipped as usize..);
+                *skip -= skipped;
+                bytes.truncate((*take).min(bytes.len() as u64) as usize);
+                *take -= bytes.len() as u64;
+                bytes
+            });
+            future::ready(Some(chunk))
+        })
+        .try_filter(|bytes| future::ready(!bytes.is_empty()))
+        .boxed();
+    audio
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange;
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-499"),
+            Some(ByteRange::From {
+                start: 0,
+                end: Some(499)
+            })
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=500-"),
+            Some(ByteRange::From {
+                start: 500,
+                end: None
+            })
+        );
+        assert_eq!(ByteRange::parse("bytes=-500"), Some(ByteRange::Suffix(500)));
+    }
+
+    #[test]
+    fn rejects_malformed_and_multiple_ranges() {
+        assert_eq!(ByteRange::parse("bytes=500-100"), None);
+        assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+        assert_eq!(ByteRange::parse("items=0-1"), None);
+        assert_eq!(ByteRange::parse("bytes=abc-"), None);
+        assert_eq!(ByteRange::parse("bytes=-"), None);
+    }
+
+    #[test]
+    fn resolves_suffix_ranges() {
+        assert_eq!(ByteRange::Suffix(500).resolve(1000), Some((500, 999)));
+        // asking for more than the file has returns the whole file
+        assert_eq!(ByteRange::Suffix(5000).resolve(1000), Some((0, 999)));
+        assert_eq!(ByteRange::Suffix(0).resolve(1000), None);
+        assert_eq!(ByteRange::Suffix(500).resolve(0), None);
+    }
+
+    #[test]
+    fn resolves_open_ended_ranges() {
+        let open = |start| ByteRange::From { start, end: None };
+        assert_eq!(open(0).resolve(1000), Some((0, 999)));
+        assert_eq!(open(999).resolve(1000), Some((999, 999)));
+        assert_eq!(open(1000).resolve(1000), None);
+        assert_eq!(open(0).resolve(0), None);
+    }
+
+    #[test]
+    fn clamps_ranges_past_the_end() {
+        let range = ByteRange::From {
+            start: 900,
+            end: Some(1999),
+        };
+        assert_eq!(range.resolve(1000), Some((900, 999)));
+    }
+}
@@ -0,0 +1,194 @@
+// Bookmarks and stars, and exporting them as Markdown or CSV
+
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use super::rss::PodcastEpisodeDbResult;
+
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Bookmark {
+    pub id: Uuid,
+    pub episode_id: Uuid,
+    pub episode_title: String,
+    pub channel_id: Uuid,
+    pub channel_title: String,
+    /// Seconds into the episode
+    pub position: f64,
+    pub note: Option<String>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub updated_at: DateTime<Utc>,
+    /// Public link opening the episode at the bookmark, filled in after loading
+    #[sqlx(default)]
+    pub share_url: String,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+pub fn share_url(public_url: &str, bookmark_id: Uuid) -> String {
+    format!(
+        "{}/share/bookmark/{bookmark_id}",
+        public_url.trim_end_matches('/')
+    )
+}
+
+/// The enclosure with a media fragment, which browsers start playing at the given second
+pub fn share_target(audio_link: &str, position: f64) -> String {
+    let audio_link = audio_link.split('#').next().unwrap_or(audio_link);
+    format!("{audio_link}#t={}", position.floor() as u64)
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour on
+pub fn format_timestamp(position: f64) -> String {
+    let seconds = position.max(0.0).floor() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+/// Starred episodes followed by the bookmarks, grouped by episode in the order given
+pub fn build_export(
+    format: ExportFormat,
+    title: &str,
+    starred: &[PodcastEpisodeDbResult],
+    bookmarks: &[Bookmark],
+) -> String {
+    match format {
+        ExportFormat::Markdown => build_markdown(title, starred, bookmarks),
+        ExportFormat::Csv => build_csv(starred, bookmarks),
+    }
+}
+
+fn episode_link(episode: &PodcastEpisodeDbResult) -> &str {
+    if episode.website_link.is_empty() {
+        &episode.audio_link
+    } else {
+        &episode.website_link
+    }
+}
+
+fn build_markdown(
+    title: &str,
+    starred: &[PodcastEpisodeDbResult],
+    bookmarks: &[Bookmark],
+) -> String {
+    let mut document = format!("# {title}\n");
+    if !starred.is_empty() {
+        document.push_str("\n## Starred\n\n");
+        for episode in starred {
+            let _ = writeln!(
+                document,
+                "- {}: [{}]({})",
+                episode.channel_title,
+                episode.title,
+                episode_link(episode)
+            );
+        }
+    }
+    if !bookmarks.is_empty() {
+        document.push_str("\n## Bookmarks\n");
+        let mut current_episode = None;
+        for bookmark in bookmarks {
+            if current_episode != Some(bookmark.episode_id) {
+                current_episode = Some(bookmark.episode_id);
+                let _ = writeln!(
+                    document,
+                    "\n### {}: {}\n",
+                    bookmark.channel_title, bookmark.episode_title
+                );
+            }
+            let _ = write!(
+                document,
+                "- [{}]({})",
+                format_timestamp(bookmark.position),
+                bookmark.share_url
+            );
+            match &bookmark.note {
+                // keeps multi-line notes inside their list item
+                Some(note) => {
+                    let _ = writeln!(document, " {}", note.replace('\n', "\n  "));
+                }
+                None => document.push('\n'),
+            }
+        }
+    }
+    document
+}
+
+fn build_csv(starred: &[PodcastEpisodeDbResult], bookmarks: &[Bookmark]) -> String {
+    let mut document = String::from("type,channel,episode,timestamp,seconds,note,link\r\n");
+    for episode in starred {
+        push_csv_row(
+            &mut document,
+            &[
+                "star",
+                &episode.channel_title,
+                &episode.title,
+                "",
+                "",
+                "",
+                episode_link(episode),
+            ],
+        );
+    }
+    for bookmark in bookmarks {
+        push_csv_row(
+            &mut document,
+            &[
+                "bookmark",
+                &bookmark.channel_title,
+                &bookmark.episode_title,
+                &format_timestamp(bookmark.position),
+                &bookmark.position.to_string(),
+                bookmark.note.as_deref().unwrap_or_default(),
+                &bookmark.share_url,
+            ],
+        );
+    }
+    document
+}
+
+/// Quotes fields as RFC 4180 asks for
+fn push_csv_row(document: &mut String, fields: &[&str]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            document.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            let _ = write!(document, "\"{}\"", field.replace('"', "\"\""));
+        } else {
+            document.push_str(field);
+        }
+    }
+    document.push_str("\r\n");
+}
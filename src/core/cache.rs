@@ -1,13 +1,13 @@
 // HTTP caching (RFC 7234) to speed up fetching RSS feed results with Redis
 
 use derivative::Derivative;
-use http::{request, response};
+use http::{header, request, response, HeaderValue};
 use http_cache_semantics::{AfterResponse, BeforeRequest, CachePolicy, ResponseLike};
 
 use reqwest::{RequestBuilder, Response};
 use serde::{Deserialize, Serialize};
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use url::Url;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -29,6 +29,12 @@ impl CachedHttpResponse {
             Self::Miss(resp) => resp,
         }
     }
+
+    pub fn into_response(self) -> HttpResponse {
+        match self {
+            Self::Hit(resp) | Self::Miss(resp) => resp,
+        }
+    }
 }
 
 /// A basic generic type that represents an HTTP response
@@ -82,23 +88,87 @@ impl HttpResponse {
         }
     }
 
-    async fn from_reqwest(response: Response) -> Self {
+    /// Marks successful responses without caching headers as fresh for `ttl`, authenticated requests included
+    fn set_fallback_ttl(&mut self, ttl: Duration) {
+        if !self.status.is_success()
+            || self.headers.contains_key(header::CACHE_CONTROL)
+            || self.headers.contains_key(header::EXPIRES)
+        {
+            return;
+        }
+        let value = format!("public, max-age={}", ttl.as_secs());
+        if let Ok(value) = HeaderValue::try_from(value) {
+            self.headers.insert(header::CACHE_CONTROL, value);
+        }
+    }
+
+    async fn from_reqwest(response: Response) -> reqwest::Result<Self> {
         let headers = response.headers().to_owned();
         let status = response.status();
         let version = response.version();
         let url = response.url().to_owned();
-        let body: Vec<_> = response.bytes().await.unwrap().to_vec();
+        let body: Vec<_> = response.bytes().await?.to_vec();
 
-        Self {
+        Ok(Self {
             body,
             headers,
             status,
             url,
             version,
-        }
+        })
     }
 }
 
+/// Remaining freshness lifetime of a cached response, zero once it has gone stale
+pub async fn get_cache_ttl(
+    con: &mut redis::aio::ConnectionManager,
+    source: &str,
+) -> Option<Duration> {
+    let cached_item_json = redis::cmd("GET")
+        .arg(source)
+        .query_async::<_, String>(con)
+        .await
+        .ok()?;
+    let RedisCacheItem { policy, .. } = serde_json::from_str(&cached_item_json).ok()?;
+    Some(policy.time_to_live(SystemTime::now()))
+}
+
+/// The stored response for `source`, fresh or not
+pub async fn get_cached_response(
+    con: &mut redis::aio::ConnectionManager,
+    source: &str,
+) -> Option<HttpResponse> {
+    let cached_item_json = redis::cmd("GET")
+        .arg(source)
+        .query_async::<_, String>(con)
+        .await
+        .ok()?;
+    let RedisCacheItem {
+        cached_response, ..
+    } = serde_json::from_str(&cached_item_json).ok()?;
+    Some(cached_response)
+}
+
+/// Drops the stored response, so the next fetch of `source` goes to the server
+pub async fn invalidate_cached_response(con: &mut redis::aio::ConnectionManager, source: &str) {
+    let _result = redis::cmd("DEL")
+        .arg(source)
+        .query_async::<_, ()>(con)
+        .await;
+}
+
+/// Fetches a file referenced by a feed, such as a transcript or chapters, through the cache
+pub async fn get_file_with_cache(
+    url: &str,
+    con: &mut redis::aio::ConnectionManager,
+) -> reqwest::Result<HttpResponse> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let response = get_response_with_cache(client.get(url), con, url).await?;
+    Ok(response.into_response())
+}
+
 fn update_request_parts(request: RequestBuilder, parts: request::Parts) -> RequestBuilder {
     request.headers(parts.headers)
 }
@@ -107,24 +177,45 @@ pub async fn get_response_with_cache(
     request_builder: RequestBuilder,
     con: &mut redis::aio::ConnectionManager,
     source: &str,
-) -> CachedHttpResponse {
-    let orig_request = request_builder.try_clone().unwrap().build().unwrap();
+) -> reqwest::Result<CachedHttpResponse> {
+    fetch_with_cache(request_builder, con, source, None).await
+}
 
-    // try to pull from cache if possible
-    if let Ok(prev_cached_item_json) = redis::cmd("GET")
+/// Same as `get_response_with_cache`, but responses that don't state their own freshness are kept for `ttl`.
+/// Meant for APIs that send no caching headers at all.
+pub async fn get_response_with_fallback_ttl(
+    request_builder: RequestBuilder,
+    con: &mut redis::aio::ConnectionManager,
+    source: &str,
+    ttl: Duration,
+) -> reqwest::Result<CachedHttpResponse> {
+    fetch_with_cache(request_builder, con, source, Some(ttl)).await
+}
+
+async fn fetch_with_cache(
+    request_builder: RequestBuilder,
+    con: &mut redis::aio::ConnectionManager,
+    source: &str,
+    fallback_ttl: Option<Duration>,
+) -> reqwest::Result<CachedHttpResponse> {
+    let orig_request = request_builder.try_clone().unwrap().build()?;
+
+    // try to pull from cache if possible, unreadable entries are treated as a miss
+    let prev_cached_item = redis::cmd("GET")
         .arg(source)
         .query_async::<_, String>(con)
         .await
+        .ok()
+        .and_then(|json| serde_json::from_str::<RedisCacheItem>(&json).ok());
+    if let Some(RedisCacheItem {
+        policy,
+        mut cached_response,
+    }) = prev_cached_item
     {
-        let RedisCacheItem {
-            policy,
-            mut cached_response,
-        } = serde_json::from_str(&prev_cached_item_json).unwrap();
-
         match policy.before_request(&orig_request, SystemTime::now()) {
             BeforeRequest::Fresh(parts) => {
                 cached_response.update_headers(&parts);
-                CachedHttpResponse::Hit(cached_response.clone())
+                Ok(CachedHttpResponse::Hit(cached_response.clone()))
             }
             BeforeRequest::Stale {
                 request,
@@ -132,17 +223,20 @@ pub async fn get_response_with_cache(
             } => {
                 // update parts
                 let request_builder = update_request_parts(request_builder, request);
-                let orig_request = request_builder.try_clone().unwrap().build().unwrap();
+                let orig_request = request_builder.try_clone().unwrap().build()?;
 
-                let response = request_builder.send().await.unwrap();
-                let mut response = HttpResponse::from_reqwest(response).await;
+                let response = request_builder.send().await?;
+                let mut response = HttpResponse::from_reqwest(response).await?;
+                if let Some(ttl) = fallback_ttl {
+                    response.set_fallback_ttl(ttl);
+                }
 
                 match policy.after_response(&orig_request, &response, SystemTime::now()) {
                     AfterResponse::NotModified(_, parts) => {
                         // 304
                         // use cached body, update headers from parts
                         response.update_headers(&parts);
-                        CachedHttpResponse::Hit(cached_response.clone())
+                        Ok(CachedHttpResponse::Hit(cached_response.clone()))
                     }
                     AfterResponse::Modified(policy, parts) => {
                         // 200
@@ -159,14 +253,17 @@ pub async fn get_response_with_cache(
                             .arg(cache_item_json)
                             .query_async::<_, ()>(con)
                             .await;
-                        CachedHttpResponse::Miss(cache_item.cached_response)
+                        Ok(CachedHttpResponse::Miss(cache_item.cached_response))
                     }
                 }
             }
         }
     } else {
-        let response = request_builder.send().await.unwrap();
-        let response = HttpResponse::from_reqwest(response).await;
+        let response = request_builder.send().await?;
+        let mut response = HttpResponse::from_reqwest(response).await?;
+        if let Some(ttl) = fallback_ttl {
+            response.set_fallback_ttl(ttl);
+        }
         let cache_policy = CachePolicy::new(&orig_request, &response);
         let response = if cache_policy.is_storable() {
             println!("STORING IN CACHE");
@@ -186,6 +283,6 @@ pub async fn get_response_with_cache(
         } else {
             response
         };
-        CachedHttpResponse::Miss(response)
+        Ok(CachedHttpResponse::Miss(response))
     }
 }
@@ -0,0 +1,204 @@
+// Podcast directory lookups through a PodcastIndex-compatible API, see https://podcastindex-org.github.io/docs-api/
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use url::Url;
+use uuid::Uuid;
+
+use super::cache::get_response_with_fallback_ttl;
+use super::rss::PodcastChannel;
+use crate::config::Config;
+
+// PodcastIndex sends no caching headers, and directory listings change slowly
+const DIRECTORY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// A feed listed in the directory, in the same shape as the channels we store
+#[derive(Serialize, Debug, Clone)]
+pub struct DirectoryFeed {
+    /// PodcastIndex feed id, as used by `podcasts/byfeedid`
+    pub directory_id: i64,
+    #[serde(flatten)]
+    pub channel: PodcastChannel,
+    /// Whether the requesting user already subscribes to the feed, filled in by the caller
+    pub subscribed: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexFeed {
+    id: i64,
+    title: String,
+    url: String,
+    link: Option<String>,
+    description: Option<String>,
+    author: Option<String>,
+    image: Option<String>,
+    artwork: Option<String>,
+    categories: Option<BTreeMap<String, String>>,
+    episode_count: Option<i64>,
+}
+
+impl From<IndexFeed> for DirectoryFeed {
+    fn from(feed: IndexFeed) -> Self {
+        // the API uses empty strings for missing values as often as nulls
+        let non_empty = |value: Option<String>| value.filter(|v| !v.trim().is_empty());
+        let tags = feed
+            .categories
+            .map(|categories| categories.into_values().collect::<Vec<_>>().join(","))
+            .filter(|tags| !tags.is_empty());
+        Self {
+            directory_id: feed.id,
+            channel: PodcastChannel {
+                // stand-in until the feed is matched against a stored channel
+                id: Uuid::new_v5(&Uuid::NAMESPACE_URL, feed.url.as_bytes()),
+                title: feed.title,
+                website_link: non_empty(feed.link).unwrap_or_default(),
+                author: non_empty(feed.author),
+                description: non_empty(feed.description),
+                tags,
+                num_episodes: feed.episode_count,
+                image: non_empty(feed.artwork).or_else(|| non_empty(feed.image)),
+                rss_link: feed.url,
+                ..Default::default()
+            },
+            subscribed: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FeedsResponse {
+    #[serde(default)]
+    feeds: Vec<IndexFeed>,
+}
+
+#[derive(Deserialize)]
+struct FeedResponse {
+    // an empty array rather than null when nothing matched
+    #[serde(default)]
+    feed: serde_json::Value,
+}
+
+pub struct DirectoryClient<'a> {
+    base_url: &'a str,
+    key: &'a str,
+    secret: &'a str,
+}
+
+impl<'a> DirectoryClient<'a> {
+    /// `None` unless API credentials are configured
+    pub fn from_config(config: &'a Config) -> Option<Self> {
+        Some(Self {
+            base_url: config.podcastindex_url.trim_end_matches('/'),
+            key: config.podcastindex_key.as_deref()?,
+            secret: config.podcastindex_secret.as_deref()?,
+        })
+    }
+
+    pub async fn search(
+        &self,
+        term: &str,
+        redis_conn: &mut redis::aio::ConnectionManager,
+    ) -> Result<Vec<DirectoryFeed>> {
+        let response: FeedsResponse = self
+            .get("search/byterm", &[("q", term.to_string())], redis_conn)
+            .await?;
+        Ok(response
+            .feeds
+            .into_iter()
+            .map(DirectoryFeed::from)
+            .collect())
+    }
+
+    /// `language` takes comma separated codes, `category` comma separated names or ids
+    pub async fn trending(
+        &self,
+        max: Option<u32>,
+        language: Option<&str>,
+        category: Option<&str>,
+        redis_conn: &mut redis::aio::ConnectionManager,
+    ) -> Result<Vec<DirectoryFeed>> {
+        let mut params = vec![];
+        if let Some(max) = max {
+            params.push(("max", max.to_string()));
+        }
+        if let Some(language) = language {
+            params.push(("lang", language.to_string()));
+        }
+        if let Some(category) = category {
+            params.push(("cat", category.to_string()));
+        }
+        let response: FeedsResponse = self.get("podcasts/trending", &params, redis_conn).await?;
+        Ok(response
+            .feeds
+            .into_iter()
+            .map(DirectoryFeed::from)
+            .collect())
+    }
+
+    pub async fn feed_by_id(
+        &self,
+        id: i64,
+        redis_conn: &mut redis::aio::ConnectionManager,
+    ) -> Result<Option<DirectoryFeed>> {
+        let response: FeedResponse = self
+            .get("podcasts/byfeedid", &[("id", id.to_string())], redis_conn)
+            .await?;
+        Ok(serde_json::from_value::<IndexFeed>(response.feed)
+            .ok()
+            .map(DirectoryFeed::from))
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        params: &[(&str, String)],
+        redis_conn: &mut redis::aio::ConnectionManager,
+    ) -> Result<T> {
+        let mut url = Url::parse(&format!("{}/{endpoint}", self.base_url))?;
+        url.query_pairs_mut().extend_pairs(params);
+
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("librepod/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        let (date, authorization) = self.auth_headers();
+        let request = client
+            .get(url.clone())
+            .header("X-Auth-Key", self.key)
+            .header("X-Auth-Date", date)
+            .header("Authorization", authorization);
+
+        // keyed by the URL alone, the auth headers change with every request
+        let response =
+            get_response_with_fallback_ttl(request, redis_conn, url.as_str(), DIRECTORY_TTL)
+                .await?
+                .into_response();
+        if !response.status.is_success() {
+            return Err(anyhow!(
+                "directory responded to {endpoint} with {}",
+                response.status
+            ));
+        }
+        Ok(serde_json::from_slice(&response.body)?)
+    }
+
+    /// The API authenticates with sha1(key + secret + unix time), sent along with that time
+    fn auth_headers(&self) -> (String, String) {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        let mut hasher = Sha1::new();
+        hasher.update(self.key);
+        hasher.update(self.secret);
+        hasher.update(&date);
+        (date, hex::encode(hasher.finalize()))
+    }
+}
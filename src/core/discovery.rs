@@ -0,0 +1,284 @@
+// Turns whatever link a user pastes (share links, show websites, podcast URL schemes) into a feed
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::cache::{get_cached_response, get_response_with_fallback_ttl};
+use super::limiter::FetchLimiter;
+use super::rss::{get_rss_data, FetchResult, RssData};
+use crate::config::Config;
+
+// Apple's lookup API ties an id to a feed for good, no need to ask again soon
+const LOOKUP_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+
+const FEED_TYPES: [&str; 3] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+];
+
+lazy_static! {
+    static ref RE_LINK_TAG: Regex = Regex::new(r"(?is)<link\b[^>]*>").expect("Invalid regex");
+    static ref RE_ATTRIBUTE: Regex =
+        Regex::new(r#"(?is)([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+            .expect("Invalid regex");
+    static ref RE_APPLE_ID: Regex = Regex::new(r"^id(\d+)$").expect("Invalid regex");
+    static ref RE_OVERCAST_ID: Regex = Regex::new(r"^itunes(\d+)$").expect("Invalid regex");
+}
+
+/// A feed advertised by a web page
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FeedCandidate {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+pub enum FeedResolution {
+    Resolved(Box<RssData>),
+    /// The feed at this link was served from cache, so the server already knows its channel
+    Cached(String),
+    /// The page advertises several feeds and the user has to pick one
+    Candidates(Vec<FeedCandidate>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LookupResponse {
+    #[serde(default)]
+    results: Vec<LookupResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LookupResult {
+    feed_url: Option<String>,
+}
+
+/// Fetches the feed behind `input`, which may be a feed, a share link or a page advertising feeds
+pub async fn resolve_feed(
+    input: &str,
+    config: &Config,
+    limiter: &FetchLimiter,
+    redis_conn: &mut redis::aio::ConnectionManager,
+) -> Result<FeedResolution> {
+    let url = normalize_link(input)?;
+    let link = match share_link_feed(&url, config, redis_conn).await? {
+        Some(feed) => feed,
+        None => url.to_string(),
+    };
+
+    // most links are feeds already, so only pages that fail to parse get searched for feeds
+    let fetched = {
+        let _permit = limiter.acquire(&link).await;
+        get_rss_data(&link, redis_conn).await
+    };
+    let parse_error = match fetched {
+        FetchResult::ParseError(parse_error) => parse_error,
+        // a show page served from cache isn't parsed again, so check what was stored
+        FetchResult::NotModified => match cached_parse_error(&link, redis_conn).await {
+            Some(parse_error) => parse_error,
+            None => return Ok(FeedResolution::Cached(link)),
+        },
+        result => return Ok(FeedResolution::Resolved(Box::new(result.into_data()?))),
+    };
+    let mut candidates = {
+        let _permit = limiter.acquire(&link).await;
+        discover_feeds(&link).await
+    }
+    .with_context(|| format!("not a feed: {parse_error}"))?;
+    match candidates.len() {
+        0 => Err(anyhow!(
+            "{link} is neither a feed nor a page linking to one"
+        )),
+        1 => {
+            let candidate = candidates.remove(0);
+            let fetched = {
+                let _permit = limiter.acquire(&candidate.url).await;
+                get_rss_data(&candidate.url, redis_conn).await
+            };
+            match fetched {
+                FetchResult::NotModified => Ok(FeedResolution::Cached(candidate.url)),
+                result => Ok(FeedResolution::Resolved(Box::new(result.into_data()?))),
+            }
+        }
+        _ => Ok(FeedResolution::Candidates(candidates)),
+    }
+}
+
+/// Why the cached response for `link` isn't a feed, `None` when it is one or nothing is cached
+async fn cached_parse_error(
+    link: &str,
+    redis_conn: &mut redis::aio::ConnectionManager,
+) -> Option<String> {
+    let response = get_cached_response(redis_conn, link).await?;
+    feed_rs::parser::parse(&response.body[..])
+        .err()
+        .map(|err| err.to_string())
+}
+
+/// Accepts bare hosts and the feed/itpc/pcast/podcast URL schemes podcast apps register
+fn normalize_link(input: &str) -> Result<Url> {
+    let input = input.trim();
+    let input = match input.split_once("://") {
+        Some((scheme, rest))
+            if ["feed", "itpc", "pcast", "podcast"].contains(&scheme.to_lowercase().as_str()) =>
+        {
+            format!("http://{rest}")
+        }
+        Some(_) => input.to_string(),
+        None => format!("https://{input}"),
+    };
+    let url = Url::parse(&input).with_context(|| format!("invalid link {input}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("unsupported link {url}"));
+    }
+    Ok(url)
+}
+
+/// Feed for links whose host we know how to map without scraping the page
+async fn share_link_feed(
+    url: &Url,
+    config: &Config,
+    redis_conn: &mut redis::aio::ConnectionManager,
+) -> Result<Option<String>> {
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    let host = host.trim_start_matches("www.");
+    let first_segment = url
+        .path_segments()
+        .and_then(|mut segments| segments.next())
+        .unwrap_or_default();
+    let subdomain = |domain: &str| {
+        host.strip_suffix(domain)
+            .filter(|show| !show.is_empty() && !show.contains('.') && *show != "feed")
+            .map(String::from)
+    };
+
+    let feed = match host {
+        // podcasts.apple.com/us/podcast/some-show/id123456789
+        "podcasts.apple.com" | "itunes.apple.com" => {
+            let id = url
+                .path_segments()
+                .into_iter()
+                .flatten()
+                .find_map(|segment| RE_APPLE_ID.captures(segment))
+                .map(|captures| captures[1].to_string())
+                .or_else(|| {
+                    url.query_pairs()
+                        .find(|(key, _)| key == "id")
+                        .map(|(_, id)| id.into_owned())
+                })
+                .ok_or_else(|| anyhow!("no podcast id in {url}"))?;
+            Some(itunes_lookup(&id, config, redis_conn).await?)
+        }
+        // overcast.fm/itunes123456789/some-show
+        "overcast.fm" => match RE_OVERCAST_ID.captures(first_segment) {
+            Some(captures) => Some(itunes_lookup(&captures[1], config, redis_conn).await?),
+            None => None,
+        },
+        "buzzsprout.com"
+            if !first_segment.is_empty() && first_segment.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            Some(format!("https://feeds.buzzsprout.com/{first_segment}.rss"))
+        }
+        _ => {
+            if let Some(show) = subdomain(".podbean.com") {
+                Some(format!("https://feed.podbean.com/{show}/feed.xml"))
+            } else if let Some(show) = subdomain(".libsyn.com") {
+                Some(format!("https://{show}.libsyn.com/rss"))
+            } else {
+                subdomain(".transistor.fm")
+                    .map(|show| format!("https://feeds.transistor.fm/{show}"))
+            }
+        }
+    };
+    Ok(feed)
+}
+
+async fn itunes_lookup(
+    id: &str,
+    config: &Config,
+    redis_conn: &mut redis::aio::ConnectionManager,
+) -> Result<String> {
+    let mut url = Url::parse(&config.itunes_lookup_url)?;
+    url.query_pairs_mut()
+        .append_pair("id", id)
+        .append_pair("entity", "podcast");
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let response = get_response_with_fallback_ttl(
+        client.get(url.clone()),
+        redis_conn,
+        url.as_str(),
+        LOOKUP_TTL,
+    )
+    .await?
+    .into_response();
+    if !response.status.is_success() {
+        return Err(anyhow!("podcast lookup responded with {}", response.status));
+    }
+
+    let lookup: LookupResponse = serde_json::from_slice(&response.body)?;
+    lookup
+        .results
+        .into_iter()
+        .find_map(|result| result.feed_url)
+        .ok_or_else(|| anyhow!("no feed listed for podcast id {id}"))
+}
+
+/// Feeds a web page advertises through `<link rel="alternate">` autodiscovery
+async fn discover_feeds(page: &str) -> Result<Vec<FeedCandidate>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let response = client.get(page).send().await?.error_for_status()?;
+    let base = response.url().clone();
+    let html = response.text().await?;
+
+    let mut candidates: Vec<FeedCandidate> = vec![];
+    for tag in RE_LINK_TAG.find_iter(&html) {
+        let attr = |name: &str| {
+            RE_ATTRIBUTE
+                .captures_iter(tag.as_str())
+                .find(|captures| captures[1].eq_ignore_ascii_case(name))
+                .and_then(|captures| {
+                    captures
+                        .get(2)
+                        .or_else(|| captures.get(3))
+                        .or_else(|| captures.get(4))
+                })
+                .map(|value| unescape_attribute(value.as_str()))
+        };
+        let is_alternate = attr("rel").is_some_and(|rel| {
+            rel.split_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+        });
+        let is_feed = attr("type")
+            .is_some_and(|mime| FEED_TYPES.contains(&mime.trim().to_lowercase().as_str()));
+        let Some(href) = attr("href").filter(|_| is_alternate && is_feed) else {
+            continue;
+        };
+        let Ok(url) = base.join(href.trim()) else {
+            continue;
+        };
+        if candidates.iter().all(|c| c.url != url.as_str()) {
+            candidates.push(FeedCandidate {
+                url: url.to_string(),
+                title: attr("title").filter(|title| !title.trim().is_empty()),
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+fn unescape_attribute(value: &str) -> String {
+    quick_xml::escape::unescape(value)
+        .map(|value| value.into_owned())
+        .unwrap_or_else(|_| value.to_string())
+}
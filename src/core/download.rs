@@ -0,0 +1,135 @@
+// Transfers episode files to local storage, picking up partial files where they were left off
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use http::{header, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+// generous, but a stalled host shouldn't hold on to a download slot forever
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60 * 60 * 2);
+
+pub fn file_path(storage_path: &str, episode_id: Uuid) -> PathBuf {
+    Path::new(storage_path).join(episode_id.to_string())
+}
+
+pub fn partial_path(storage_path: &str, episode_id: Uuid) -> PathBuf {
+    Path::new(storage_path).join(format!("{episode_id}.partial"))
+}
+
+/// A response from the host, positioned where the partial file ends
+pub struct FileTransfer {
+    response: reqwest::Response,
+    /// Bytes of the partial file the response continues from, 0 when starting over
+    pub offset: u64,
+    pub total_size: Option<u64>,
+    pub mime_type: Option<String>,
+    /// ETag or Last-Modified, for resuming later on
+    pub validator: Option<String>,
+}
+
+impl FileTransfer {
+    /// Requests the file, asking for the rest of `partial` when there is one we can safely resume.
+    /// `validator` is the one received when the partial file was started.
+    pub async fn start(url: &str, partial: &Path, validator: Option<&str>) -> Result<Self> {
+        let existing = fs::metadata(partial).await.map(|m| m.len()).unwrap_or(0);
+        // without a validator we can't tell whether the file changed in between
+        let resume_from = validator.filter(|_| existing > 0).map(|v| (existing, v));
+
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("librepod/", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(TRANSFER_TIMEOUT)
+            .no_gzip()
+            .no_brotli()
+            .no_deflate()
+            .build()?;
+        let mut request = client.get(url);
+        if let Some((offset, validator)) = resume_from {
+            request = request
+                .header(header::RANGE, format!("bytes={offset}-"))
+                .header(header::IF_RANGE, validator);
+        }
+        let response = request.send().await?;
+
+        let headers = response.headers();
+        let header_str = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let mime_type = header_str(header::CONTENT_TYPE);
+        // error pages served with a 200
+        if let Some(mime) = mime_type.as_ref().filter(|mime| mime.starts_with("text/")) {
+            return Err(anyhow!("host sent {mime} instead of media"));
+        }
+        let validator = header_str(header::ETAG)
+            .filter(|etag| !etag.starts_with("W/"))
+            .or_else(|| header_str(header::LAST_MODIFIED));
+
+        let (offset, total_size) = match response.status() {
+            StatusCode::OK => (0, response.content_length()),
+            StatusCode::PARTIAL_CONTENT => {
+                // bytes <start>-<end>/<total>
+                let content_range = header_str(header::CONTENT_RANGE).unwrap_or_default();
+                let (range, total) = content_range
+                    .strip_prefix("bytes ")
+                    .and_then(|range| range.split_once('/'))
+                    .ok_or_else(|| anyhow!("invalid Content-Range {content_range}"))?;
+                let start = range
+                    .split_once('-')
+                    .and_then(|(start, _)| start.parse().ok());
+                if resume_from.map(|(offset, _)| offset) != start {
+                    return Err(anyhow!("host resumed at the wrong offset"));
+                }
+                (start.unwrap_or_default(), total.parse().ok())
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                // the partial file doesn't fit the host's file, start over next time
+                fs::remove_file(partial).await.ok();
+                return Err(anyhow!("host could not resume the partial file"));
+            }
+            status => return Err(anyhow!("host responded with {status}")),
+        };
+
+        Ok(Self {
+            response,
+            offset,
+            total_size,
+            mime_type,
+            validator,
+        })
+    }
+
+    /// Writes the body after the first `offset` bytes of `partial`, returning the file's size.
+    /// What was written stays in place when the transfer breaks off.
+    pub async fn write_to(self, partial: &Path) -> Result<u64> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(self.offset > 0)
+            .truncate(self.offset == 0)
+            .open(partial)
+            .await?;
+        let mut body = self.response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        Ok(file.metadata().await?.len())
+    }
+}
+
+/// Hex encoded sha256 of a file, reads the whole file so run it on a blocking thread
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,113 @@
+// Publisher artwork kept on our own storage, so clients never have to reach out to podcast hosts
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use image::imageops::FilterType;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Widths and heights of the square bounding boxes variants are resized into
+pub const VARIANT_SIZES: [u32; 3] = [64, 256, 600];
+pub const VARIANT_MIME_TYPE: &str = "image/webp";
+
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+const WEBP_QUALITY: f32 = 80.0;
+
+pub struct DownloadedImage {
+    pub bytes: Vec<u8>,
+    /// Hex encoded sha256 of the file
+    pub content_hash: String,
+}
+
+pub struct ImageInfo {
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub async fn download_image(url: &str) -> Result<DownloadedImage> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_IMAGE_BYTES as u64)
+    {
+        return Err(anyhow!("image is larger than {MAX_IMAGE_BYTES} bytes"));
+    }
+    // the length isn't always announced, stop reading as soon as the body goes over
+    let mut bytes = Vec::new();
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() > MAX_IMAGE_BYTES {
+            return Err(anyhow!("image is larger than {MAX_IMAGE_BYTES} bytes"));
+        }
+    }
+    let content_hash = hex::encode(Sha256::digest(&bytes));
+    Ok(DownloadedImage {
+        bytes,
+        content_hash,
+    })
+}
+
+/// Where the original and the variants of an image are stored
+pub fn image_directory(storage_path: &str, id: Uuid) -> PathBuf {
+    Path::new(storage_path).join(id.to_string())
+}
+
+/// File name of the variant fitting `size`, the original when no size is asked for
+pub fn variant_file_name(size: Option<u32>) -> String {
+    match size.map(pick_variant_size) {
+        Some(size) => format!("{size}.webp"),
+        None => "original".to_string(),
+    }
+}
+
+/// The smallest variant at least as large as requested, or the largest one we have
+pub fn pick_variant_size(requested: u32) -> u32 {
+    VARIANT_SIZES
+        .into_iter()
+        .find(|size| *size >= requested)
+        .unwrap_or(VARIANT_SIZES[VARIANT_SIZES.len() - 1])
+}
+
+/// Decodes the image and writes it to `directory` along with its WebP variants.
+/// CPU heavy, so run it on a blocking thread.
+pub fn store_variants(bytes: &[u8], directory: &Path) -> Result<ImageInfo> {
+    let format = image::guess_format(bytes)?;
+    let original = image::load_from_memory_with_format(bytes, format)?;
+    fs::create_dir_all(directory)?;
+
+    for size in VARIANT_SIZES {
+        // never upscale, small artwork is served as is
+        let resized = if original.width() <= size && original.height() <= size {
+            original.to_rgba8()
+        } else {
+            original.resize(size, size, FilterType::Lanczos3).to_rgba8()
+        };
+        let encoded = webp::Encoder::from_rgba(&resized, resized.width(), resized.height())
+            .encode(WEBP_QUALITY);
+        write_atomically(&directory.join(variant_file_name(Some(size))), &encoded)?;
+    }
+    write_atomically(&directory.join(variant_file_name(None)), bytes)?;
+
+    Ok(ImageInfo {
+        mime_type: format.to_mime_type().to_string(),
+        width: original.width(),
+        height: original.height(),
+    })
+}
+
+// files are replaced while they may be served, readers should never see half of one
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, contents)?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
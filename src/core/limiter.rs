@@ -0,0 +1,51 @@
+// Bounds how many feeds are fetched at once, both overall and against any single host
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+use url::Url;
+
+pub struct FetchLimiter {
+    global: Semaphore,
+    per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Held for the duration of a fetch, releases both slots when dropped
+pub struct FetchPermit<'a> {
+    _host: OwnedSemaphorePermit,
+    _global: SemaphorePermit<'a>,
+}
+
+impl FetchLimiter {
+    pub fn new(global: usize, per_host: usize) -> Self {
+        Self {
+            global: Semaphore::new(global.max(1)),
+            per_host: per_host.max(1),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn acquire(&self, url: &str) -> FetchPermit<'_> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+        let host_semaphore = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_host)))
+            .clone();
+
+        // wait for the host first so a busy host doesn't hold on to a global slot
+        let host_permit = host_semaphore.acquire_owned().await.unwrap();
+        let global_permit = self.global.acquire().await.unwrap();
+        FetchPermit {
+            _host: host_permit,
+            _global: global_permit,
+        }
+    }
+}
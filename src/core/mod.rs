@@ -1,4 +1,14 @@
 // Core logic lies here
+pub(crate) mod audio;
+pub(crate) mod bookmark;
 pub(crate) mod cache;
+pub(crate) mod directory;
+pub(crate) mod discovery;
+pub(crate) mod download;
+pub(crate) mod image;
+pub(crate) mod limiter;
+pub(crate) mod opml;
 pub(crate) mod rss;
+pub(crate) mod scheduler;
 pub(crate) mod user;
+pub(crate) mod websub;
@@ -0,0 +1,131 @@
+// OPML (Outline Processor Markup Language) import & export of subscriptions
+
+use std::io::Cursor;
+
+use anyhow::Result;
+use chrono::Utc;
+use quick_xml::events::{BytesDecl, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use super::rss::PodcastChannel;
+
+/// A single feed entry pulled from an OPML `outline` element
+#[derive(Debug, Clone)]
+pub struct OpmlOutline {
+    pub title: Option<String>,
+    pub xml_url: String,
+}
+
+/// Collects every `outline` carrying an `xmlUrl`, including those nested inside category outlines
+pub fn parse_opml(document: &[u8]) -> Result<Vec<OpmlOutline>> {
+    let mut reader = Reader::from_reader(document);
+    reader.trim_text(true);
+
+    let mut outlines = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name().as_ref() == b"outline" =>
+            {
+                if let Some(outline) = outline_from_element(&element, &reader)? {
+                    outlines.push(outline);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(outlines)
+}
+
+fn outline_from_element(
+    element: &BytesStart,
+    reader: &Reader<&[u8]>,
+) -> Result<Option<OpmlOutline>> {
+    let (mut text, mut title, mut xml_url) = (None, None, None);
+    for attr in element.attributes() {
+        let attr = attr?;
+        let value = attr.decode_and_unescape_value(reader)?.trim().to_string();
+        if value.is_empty() {
+            continue;
+        }
+        // OPML 1.0 exporters aren't consistent about attribute casing
+        let key = attr.key.local_name();
+        let key = key.as_ref();
+        if key.eq_ignore_ascii_case(b"xmlUrl") {
+            xml_url = Some(value);
+        } else if key.eq_ignore_ascii_case(b"title") {
+            title = Some(value);
+        } else if key.eq_ignore_ascii_case(b"text") {
+            text = Some(value);
+        }
+    }
+
+    Ok(xml_url.map(|xml_url| OpmlOutline {
+        title: title.or(text),
+        xml_url,
+    }))
+}
+
+/// Builds an OPML 2.0 document with one outline per channel
+pub fn build_opml(title: &str, channels: &[PodcastChannel]) -> Result<Vec<u8>> {
+    let mut writer = Writer::new_with_indent(Cursor::new(Vec::new()), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("opml")
+        .with_attribute(("version", "2.0"))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("head")
+                .write_inner_content(|writer| {
+                    writer
+                        .create_element("title")
+                        .write_text_content(BytesText::new(title))?;
+                    writer
+                        .create_element("dateCreated")
+                        .write_text_content(BytesText::new(&Utc::now().to_rfc2822()))?;
+                    Ok(())
+                })?;
+            writer
+                .create_element("body")
+                .write_inner_content(|writer| {
+                    for channel in channels {
+                        write_channel_outline(writer, channel)?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+
+    Ok(writer.into_inner().into_inner())
+}
+
+fn write_channel_outline(
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+    channel: &PodcastChannel,
+) -> quick_xml::Result<()> {
+    let mut outline = writer.create_element("outline").with_attributes([
+        ("type", "rss"),
+        ("text", channel.title.as_str()),
+        ("title", channel.title.as_str()),
+        ("xmlUrl", channel.rss_link.as_str()),
+        ("htmlUrl", channel.website_link.as_str()),
+    ]);
+    // OPML categories are comma separated, slash delimited paths
+    let categories = channel.tags.as_ref().map(|tags| {
+        tags.split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| format!("/{tag}"))
+            .collect::<Vec<String>>()
+            .join(",")
+    });
+    if let Some(categories) = categories.filter(|c| !c.is_empty()) {
+        outline = outline.with_attribute(("category", categories.as_str()));
+    }
+    outline.write_empty()?;
+    Ok(())
+}
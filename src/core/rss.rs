@@ -1,39 +1,244 @@
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use anyhow::anyhow;
 use chrono::{DateTime, Utc};
 use feed_rs::model::{Category, Entry, Feed};
+use http::StatusCode;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::{NsReader, Reader};
+use reqwest::redirect;
 use serde::Serialize;
+use sqlx::FromRow;
+use url::Url;
 use uuid::Uuid;
 
-use super::cache::{get_response_with_cache, CachedHttpResponse};
+use super::cache::{get_response_with_cache, invalidate_cached_response, CachedHttpResponse};
+use super::scheduler::refresh_hint;
 
 // Data fetched straight from RSS link
 pub struct RssData {
     pub channel: PodcastChannel,
     pub episodes: Vec<PodcastEpisode>,
+    pub websub: Option<WebSubLinks>,
+    pub refresh_hint: Option<Duration>,
+    /// New feed link announced through a permanent redirect or <itunes:new-feed-url>
+    pub moved_to: Option<String>,
+    /// `moved_to` is where permanent redirects led, so the feed was actually served from there
+    pub moved_by_redirect: bool,
+    /// Channel-wide `podcast:funding` and `podcast:person` tags
+    pub funding: Vec<Funding>,
+    pub persons: Vec<Person>,
+}
+
+// WebSub discovery links advertised by a feed through <link rel="hub"> and <link rel="self">
+#[derive(Debug, Clone)]
+pub struct WebSubLinks {
+    pub hub: String,
+    pub topic: String,
+}
+
+impl WebSubLinks {
+    pub fn from_feed(feed: &Feed, rss_link: &str) -> Option<Self> {
+        let find_rel = |rel: &str| {
+            feed.links
+                .iter()
+                .find(|l| l.rel.as_deref() == Some(rel))
+                .map(|l| l.href.clone())
+        };
+        find_rel("hub").map(|hub| Self {
+            hub,
+            topic: find_rel("self").unwrap_or_else(|| rss_link.to_string()),
+        })
+    }
 }
 
 impl RssData {
-    pub fn from_feed(feed: &Feed, rss_link: String) -> Option<Self> {
+    /// `body` is the raw document the feed was parsed from, scanned for tags feed_rs skips
+    pub fn from_feed(feed: &Feed, body: &[u8], rss_link: String) -> Option<Self> {
+        let websub = WebSubLinks::from_feed(feed, &rss_link);
         let channel = PodcastChannel::from_feed(feed, rss_link);
-        if let Some(channel) = channel {
+        if let Some(mut channel) = channel {
+            let FeedExtensions {
+                channel: channel_extensions,
+                items,
+            } = scan_extensions(body);
+            channel.podcast_guid = channel_extensions.podcast_guid;
+            channel.locked = channel_extensions.locked;
+
+            let mut items = items.into_iter();
             let mut episodes = feed
                 .entries
                 .iter()
-                .filter_map(|item| PodcastEpisode::from_feed_item(&item, &channel))
+                .filter_map(|item| {
+                    let extensions = items.next().unwrap_or_default();
+                    PodcastEpisode::from_feed_item(item, extensions, &channel)
+                })
                 .collect::<Vec<PodcastEpisode>>();
             episodes.sort_by_key(|ep| ep.published);
-            Some(Self { channel, episodes })
+            Some(Self {
+                channel,
+                episodes,
+                websub,
+                refresh_hint: None,
+                moved_to: None,
+                moved_by_redirect: false,
+                funding: channel_extensions.funding,
+                persons: channel_extensions.persons,
+            })
         } else {
             None
         }
     }
+
+    /// Attaches the data to a channel we already store, since a moved or partial feed may hash to another id
+    pub fn bind_to_channel(&mut self, channel_id: Uuid) {
+        self.channel.id = channel_id;
+        for episode in self.episodes.iter_mut() {
+            episode.channel_id = channel_id;
+        }
+    }
 }
 
+/// Outcome of fetching a feed
+pub enum FetchResult {
+    /// Cached copy is still fresh, or the server confirmed it with a 304
+    NotModified,
+    Updated(Box<RssData>),
+    HttpError(StatusCode),
+    ParseError(String),
+    Timeout,
+    /// Redirect loops or redirects we refused to follow
+    Redirect(String),
+    /// Connection failures and other transport errors
+    RequestError(String),
+}
+
+/// Serializable summary of a fetch, stored as a channel's last status
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchStatus {
+    NotModified,
+    Updated,
+    HttpError,
+    ParseError,
+    Timeout,
+    Redirect,
+    RequestError,
+    /// Feed was fetched fine but its episodes couldn't be saved
+    StoreError,
+}
+
+impl FetchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotModified => "not_modified",
+            Self::Updated => "updated",
+            Self::HttpError => "http_error",
+            Self::ParseError => "parse_error",
+            Self::Timeout => "timeout",
+            Self::Redirect => "redirect",
+            Self::RequestError => "request_error",
+            Self::StoreError => "store_error",
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, Self::NotModified | Self::Updated)
+    }
+}
+
+impl FetchResult {
+    pub fn status(&self) -> FetchStatus {
+        match self {
+            Self::NotModified => FetchStatus::NotModified,
+            Self::Updated(_) => FetchStatus::Updated,
+            Self::HttpError(_) => FetchStatus::HttpError,
+            Self::ParseError(_) => FetchStatus::ParseError,
+            Self::Timeout => FetchStatus::Timeout,
+            Self::Redirect(_) => FetchStatus::Redirect,
+            Self::RequestError(_) => FetchStatus::RequestError,
+        }
+    }
+
+    pub fn error_message(&self) -> Option<String> {
+        match self {
+            Self::NotModified | Self::Updated(_) => None,
+            Self::HttpError(status) => Some(format!("server responded with {status}")),
+            Self::ParseError(msg) => Some(format!("could not parse feed: {msg}")),
+            Self::Timeout => Some("request timed out".to_string()),
+            Self::Redirect(msg) | Self::RequestError(msg) => Some(msg.clone()),
+        }
+    }
+
+    /// Freshly fetched data, anything else becomes an error describing what went wrong
+    pub fn into_data(self) -> anyhow::Result<RssData> {
+        match self {
+            Self::Updated(data) => Ok(*data),
+            Self::NotModified => Err(anyhow!("feed is cached and was not fetched again")),
+            other => Err(anyhow!(other.error_message().unwrap_or_default())),
+        }
+    }
+
+    /// Status code of the last response from the server, if one was received
+    pub fn http_status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Updated(_) => Some(StatusCode::OK),
+            Self::HttpError(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchResult {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if err.is_redirect() {
+            Self::Redirect(err.to_string())
+        } else if let Some(status) = err.status() {
+            Self::HttpError(status)
+        } else {
+            Self::RequestError(err.to_string())
+        }
+    }
+}
+
+// Redirects followed while fetching a feed, only an unbroken chain of permanent ones counts as a move
+#[derive(Default)]
+struct RedirectTrail {
+    permanent_target: Option<Url>,
+    saw_temporary: bool,
+}
+
+const MAX_REDIRECTS: usize = 10;
+
 pub async fn get_rss_data(
     source: &str,
     redis_conn: &mut redis::aio::ConnectionManager,
-) -> Option<RssData> {
+) -> FetchResult {
+    let trail = Arc::new(Mutex::new(RedirectTrail::default()));
+    let redirect_policy = {
+        let trail = trail.clone();
+        redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            let mut trail = trail.lock().unwrap();
+            let permanent = matches!(
+                attempt.status(),
+                StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+            );
+            if permanent && !trail.saw_temporary {
+                trail.permanent_target = Some(attempt.url().clone());
+            } else {
+                trail.saw_temporary = true;
+            }
+            attempt.follow()
+        })
+    };
+
     // before request
     let client = reqwest::Client::builder()
             .user_agent("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/108.0.0.0 Safari/537.36")
@@ -41,19 +246,69 @@ pub async fn get_rss_data(
             .deflate(true)
             .brotli(true)
             .timeout(Duration::from_secs(60))
+            .redirect(redirect_policy)
     .build().unwrap();
     let request = client.get(source);
-    let cached_response = get_response_with_cache(request, redis_conn, source).await;
-    // only fetch feeds not cached
+    let cached_response = match get_response_with_cache(request, redis_conn, source).await {
+        Ok(cached_response) => cached_response,
+        Err(err) => return err.into(),
+    };
+
+    let status = cached_response.reponse().status;
+    if !status.is_success() {
+        return FetchResult::HttpError(status);
+    }
+    // only parse feeds not cached
     if let CachedHttpResponse::Miss(http_response) = cached_response {
-        let feed = feed_rs::parser::parse(&http_response.body[..]).unwrap();
-        RssData::from_feed(&feed, source.to_string())
+        let feed = match feed_rs::parser::parse(&http_response.body[..]) {
+            Ok(feed) => feed,
+            Err(err) => return FetchResult::ParseError(err.to_string()),
+        };
+        let Some(mut data) = RssData::from_feed(&feed, &http_response.body, source.to_string())
+        else {
+            return FetchResult::ParseError("feed is missing a title or link".to_string());
+        };
+
+        // an explicit announcement from the publisher wins over wherever the redirects led
+        let redirected_to = trail.lock().unwrap().permanent_target.take();
+        let redirected_to = redirected_to.map(String::from);
+        data.moved_to = new_feed_url(&http_response.body)
+            .map(String::from)
+            .or(redirected_to.clone())
+            .filter(|link| link != source);
+        data.moved_by_redirect = data.moved_to.is_some() && data.moved_to == redirected_to;
+        if let Some(moved_to) = &data.moved_to {
+            data.channel.rss_link = moved_to.clone();
+        }
+        data.refresh_hint = refresh_hint(&feed, &http_response.body);
+        FetchResult::Updated(Box::new(data))
     } else {
-        None
+        FetchResult::NotModified
     }
 }
 
-#[derive(Serialize, Debug, Clone, Default)]
+/// Like `get_rss_data`, but skips the cache, for feeds whose cached copy was never stored as a channel
+pub async fn get_uncached_rss_data(
+    source: &str,
+    redis_conn: &mut redis::aio::ConnectionManager,
+) -> FetchResult {
+    invalidate_cached_response(redis_conn, source).await;
+    get_rss_data(source, redis_conn).await
+}
+
+fn new_feed_url(body: &[u8]) -> Option<Url> {
+    let mut new_feed_url = None;
+    scan_channel_header(body, |name, text| {
+        if name == b"new-feed-url" {
+            new_feed_url = Url::parse(text)
+                .ok()
+                .filter(|url| matches!(url.scheme(), "http" | "https"));
+        }
+    });
+    new_feed_url
+}
+
+#[derive(Serialize, Debug, Clone, Default, FromRow)]
 pub struct PodcastChannel {
     pub id: Uuid,
     pub title: String,
@@ -63,7 +318,12 @@ pub struct PodcastChannel {
     pub description: Option<String>,
     pub tags: Option<String>,
     pub num_episodes: Option<i64>,
+    /// Publisher's artwork URL, clients should load the local copy at `/image/:image_id` instead
     pub image: Option<String>,
+    pub podcast_guid: Option<String>,
+    /// `podcast:locked`, the publisher asks other platforms not to import the feed
+    pub locked: Option<bool>,
+    pub image_id: Option<Uuid>,
 }
 
 impl PodcastChannel {
@@ -81,6 +341,9 @@ impl PodcastChannel {
                 rss_link,
                 num_episodes: Some(feed.entries.len() as i64),
                 image: feed.logo.clone().map(|l| l.uri),
+                podcast_guid: None,
+                locked: None,
+                image_id: None,
             })
         }
     }
@@ -99,12 +362,25 @@ pub struct PodcastEpisode {
     pub content: Option<String>,
     pub tags: Option<String>,
     pub audio_link: String,
+    pub audio_type: Option<String>,
+    pub audio_size: Option<i64>,
+    /// Length in seconds
+    pub duration: Option<i32>,
+    pub season: Option<i32>,
+    pub episode_number: Option<i32>,
+    /// One of full, trailer or bonus
+    pub episode_type: Option<String>,
+    pub explicit: Option<bool>,
+    pub image: Option<String>,
+    pub transcripts: Vec<Transcript>,
+    pub chapters: Option<Chapters>,
+    pub persons: Vec<Person>,
 }
 
 // Sqlx doesn't support nesting well, and neither does rust support inheritance, so have to manually duplicate fields
 // This is the struct returned for API calls, providing additional channel context data
 // Internally we don't need this
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone, FromRow)]
 pub struct PodcastEpisodeDbResult {
     // base PodcastEpisode
     pub channel_id: Uuid,
@@ -117,30 +393,39 @@ pub struct PodcastEpisodeDbResult {
     pub content: Option<String>,
     pub tags: Option<String>,
     pub audio_link: String,
+    pub audio_type: Option<String>,
+    pub audio_size: Option<i64>,
+    pub duration: Option<i32>,
+    pub season: Option<i32>,
+    pub episode_number: Option<i32>,
+    pub episode_type: Option<String>,
+    pub explicit: Option<bool>,
+    pub image: Option<String>,
+    #[serde(with = "chrono::serde::ts_microseconds_option")]
+    pub removed_at: Option<DateTime<Utc>>,
+    pub image_id: Option<Uuid>,
 
     // channel additions
     pub channel_title: String,
     pub channel_image: Option<String>,
+    pub channel_image_id: Option<Uuid>,
+
+    // playback additions, for the user asking
+    pub position: Option<f64>,
+    #[serde(with = "chrono::serde::ts_microseconds_option")]
+    pub position_updated_at: Option<DateTime<Utc>>,
 }
 
 impl PodcastEpisode {
-    pub fn from_feed_item(item: &Entry, source: &PodcastChannel) -> Option<Self> {
-        let media = item
-            .media
-            .get(0)
-            .map(|m| {
-                m.content
-                    .get(0)
-                    .map(|m| m.url.clone().map(|u| u.to_string()))
-            })
-            .flatten()
-            .flatten();
-        if item.title.is_none()
-            || item.media.is_empty()
-            || item.links.is_empty()
-            || item.published.is_none()
-            || media.is_none()
-        {
+    pub fn from_feed_item(
+        item: &Entry,
+        extensions: ItemExtensions,
+        source: &PodcastChannel,
+    ) -> Option<Self> {
+        let media = item.media.first();
+        let enclosure = media.and_then(|m| m.content.first());
+        let audio_link = enclosure.and_then(|c| c.url.clone().map(|u| u.to_string()))?;
+        if item.title.is_none() || item.links.is_empty() || item.published.is_none() {
             None
         } else {
             Some(PodcastEpisode {
@@ -152,14 +437,345 @@ impl PodcastEpisode {
                 content: item.content.clone().map(|t| t.body.unwrap_or_default()),
                 description: item.summary.clone().map(|t| t.content),
                 tags: tags_from_categories(item.categories.clone()),
-                audio_link: media.unwrap(),
+                audio_link,
+                audio_type: enclosure
+                    .and_then(|c| c.content_type.as_ref())
+                    .map(|mime| mime.to_string()),
+                audio_size: enclosure
+                    .and_then(|c| c.size)
+                    .and_then(|size| i64::try_from(size).ok()),
+                duration: extensions.duration.or_else(|| {
+                    media
+                        .and_then(|m| m.duration.or_else(|| enclosure.and_then(|c| c.duration)))
+                        .and_then(|duration| i32::try_from(duration.as_secs()).ok())
+                }),
+                season: extensions.season,
+                episode_number: extensions.episode,
+                episode_type: extensions.episode_type,
+                explicit: extensions.explicit,
+                image: media
+                    .and_then(|m| m.thumbnails.first())
+                    .map(|thumbnail| thumbnail.image.uri.clone()),
+                transcripts: extensions.transcripts,
+                chapters: extensions.chapters,
+                persons: extensions.persons,
             })
         }
     }
 }
 
+const PODCAST_NAMESPACE: &[u8] = b"https://podcastindex.org/namespace/1.0";
+
+/// `podcast:transcript`, the linked file may be SRT, VTT, JSON or HTML
+#[derive(Serialize, Debug, Clone)]
+pub struct Transcript {
+    pub url: String,
+    pub mime_type: String,
+    pub language: Option<String>,
+    /// `captions` when the file is timed for closed captions
+    pub rel: Option<String>,
+}
+
+/// `podcast:chapters`, links a JSON chapters file
+#[derive(Serialize, Debug, Clone)]
+pub struct Chapters {
+    pub url: String,
+    pub mime_type: String,
+}
+
+/// `podcast:person`, credited on the whole channel or a single episode
+#[derive(Serialize, Debug, Clone)]
+pub struct Person {
+    pub name: String,
+    pub role: String,
+    pub group: String,
+    pub image: Option<String>,
+    pub href: Option<String>,
+}
+
+/// `podcast:funding`, a donation or membership link
+#[derive(Serialize, Debug, Clone)]
+pub struct Funding {
+    pub url: String,
+    pub message: Option<String>,
+}
+
+/// Podcasting 2.0 channel tags that feed_rs doesn't expose
+#[derive(Debug, Clone, Default)]
+pub struct ChannelExtensions {
+    pub podcast_guid: Option<String>,
+    pub locked: Option<bool>,
+    pub funding: Vec<Funding>,
+    pub persons: Vec<Person>,
+}
+
+impl ChannelExtensions {
+    fn start_podcast_element(&mut self, name: &[u8], attrs: &Attributes) {
+        match name {
+            b"funding" => {
+                if let Some(url) = attrs.get("url") {
+                    self.funding.push(Funding { url, message: None });
+                }
+            }
+            b"person" => self.persons.push(Person::from_attributes(attrs)),
+            _ => {}
+        }
+    }
+
+    fn visit_podcast(&mut self, name: &[u8], text: &str) {
+        match name {
+            b"guid" => self.podcast_guid = Some(text.to_string()),
+            b"locked" => self.locked = parse_yes_no(text),
+            b"funding" => {
+                if let Some(funding) = self.funding.last_mut() {
+                    funding.message = Some(text.to_string());
+                }
+            }
+            b"person" => {
+                if let Some(person) = self.persons.last_mut() {
+                    person.name = text.to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Per-episode iTunes and Podcasting 2.0 tags that feed_rs doesn't expose
+#[derive(Debug, Clone, Default)]
+pub struct ItemExtensions {
+    /// feed_rs reads `itunes:duration` as NPT, which gets the common `MM:SS` form wrong
+    pub duration: Option<i32>,
+    pub season: Option<i32>,
+    pub episode: Option<i32>,
+    pub episode_type: Option<String>,
+    pub explicit: Option<bool>,
+    pub transcripts: Vec<Transcript>,
+    pub chapters: Option<Chapters>,
+    pub persons: Vec<Person>,
+}
+
+impl ItemExtensions {
+    fn visit(&mut self, name: &[u8], text: &str) {
+        match name {
+            b"duration" => self.duration = parse_itunes_duration(text),
+            b"season" => self.season = text.parse().ok(),
+            b"episode" => self.episode = text.parse().ok(),
+            b"episodeType" => {
+                let episode_type = text.to_lowercase();
+                self.episode_type = matches!(episode_type.as_str(), "full" | "trailer" | "bonus")
+                    .then_some(episode_type);
+            }
+            b"explicit" => {
+                self.explicit = match text.to_lowercase().as_str() {
+                    "true" | "explicit" => Some(true),
+                    "false" | "clean" => Some(false),
+                    other => parse_yes_no(other),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn start_podcast_element(&mut self, name: &[u8], attrs: &Attributes) {
+        match name {
+            b"transcript" => {
+                if let (Some(url), Some(mime_type)) = (attrs.get("url"), attrs.get("type")) {
+                    self.transcripts.push(Transcript {
+                        url,
+                        mime_type,
+                        language: attrs.get("language"),
+                        rel: attrs.get("rel"),
+                    });
+                }
+            }
+            b"chapters" => {
+                if let (Some(url), Some(mime_type)) = (attrs.get("url"), attrs.get("type")) {
+                    self.chapters = Some(Chapters { url, mime_type });
+                }
+            }
+            b"person" => self.persons.push(Person::from_attributes(attrs)),
+            _ => {}
+        }
+    }
+
+    fn visit_podcast(&mut self, name: &[u8], text: &str) {
+        if name == b"person" {
+            if let Some(person) = self.persons.last_mut() {
+                person.name = text.to_string();
+            }
+        }
+    }
+}
+
+impl Person {
+    /// The name comes from the element text, read after the attributes
+    fn from_attributes(attrs: &Attributes) -> Self {
+        Self {
+            name: String::new(),
+            role: attrs
+                .get("role")
+                .map_or_else(|| "host".to_string(), |role| role.to_lowercase()),
+            group: attrs
+                .get("group")
+                .map_or_else(|| "cast".to_string(), |group| group.to_lowercase()),
+            image: attrs.get("img"),
+            href: attrs.get("href"),
+        }
+    }
+}
+
+/// Trimmed, non-empty attribute values of an element keyed by local name
+struct Attributes(Vec<(Vec<u8>, String)>);
+
+impl Attributes {
+    fn new(element: &BytesStart, reader: &Reader<&[u8]>) -> Self {
+        let attrs = element
+            .attributes()
+            .filter_map(|attr| attr.ok())
+            .filter_map(|attr| {
+                let value = attr.decode_and_unescape_value(reader).ok()?;
+                let value = value.trim();
+                (!value.is_empty())
+                    .then(|| (attr.key.local_name().as_ref().to_vec(), value.to_string()))
+            })
+            .collect();
+        Self(attrs)
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name.as_bytes())
+            .map(|(_, value)| value.clone())
+    }
+}
+
+/// Extension tags of the channel and of every item or entry, in document order so they line up with feed_rs entries
+#[derive(Debug, Default)]
+pub struct FeedExtensions {
+    pub channel: ChannelExtensions,
+    pub items: Vec<ItemExtensions>,
+}
+
+pub fn scan_extensions(body: &[u8]) -> FeedExtensions {
+    let mut reader = NsReader::from_reader(body);
+    reader.trim_text(true).expand_empty_elements(true);
+
+    let mut extensions = FeedExtensions::default();
+    let mut in_item = false;
+    let mut current = Vec::new();
+    // podcast:guid and podcast:season would otherwise be mistaken for their RSS and iTunes namesakes
+    let mut in_podcast_namespace = false;
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_resolved_event_into(&mut buf) {
+            Ok((namespace, Event::Start(element))) => {
+                in_podcast_namespace = matches!(
+                    namespace,
+                    ResolveResult::Bound(Namespace(namespace)) if namespace == PODCAST_NAMESPACE
+                );
+                current = element.local_name().as_ref().to_vec();
+                if current == b"item" || current == b"entry" {
+                    in_item = true;
+                    extensions.items.push(ItemExtensions::default());
+                } else if in_podcast_namespace {
+                    let attrs = Attributes::new(&element, &reader);
+                    match extensions.items.last_mut() {
+                        Some(item) if in_item => item.start_podcast_element(&current, &attrs),
+                        _ => extensions.channel.start_podcast_element(&current, &attrs),
+                    }
+                }
+            }
+            Ok((_, Event::Text(text))) => {
+                if let Ok(text) = text.unescape() {
+                    let text = text.trim();
+                    match extensions.items.last_mut() {
+                        Some(item) if in_item && in_podcast_namespace => {
+                            item.visit_podcast(&current, text)
+                        }
+                        Some(item) if in_item => item.visit(&current, text),
+                        _ if in_podcast_namespace => {
+                            extensions.channel.visit_podcast(&current, text)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Ok((_, Event::End(element))) => {
+                let name = element.local_name();
+                if name.as_ref() == b"item" || name.as_ref() == b"entry" {
+                    in_item = false;
+                }
+                current.clear();
+                in_podcast_namespace = false;
+            }
+            Ok((_, Event::Eof)) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    // persons are only known to be valid once their name was read
+    extensions.channel.persons.retain(|p| !p.name.is_empty());
+    for item in extensions.items.iter_mut() {
+        item.persons.retain(|p| !p.name.is_empty());
+    }
+    extensions
+}
+
+/// Visits the trimmed text of each element in the channel header, i.e. everything before the first episode.
+/// Covers extensions feed_rs doesn't model, elements are matched by local name so any namespace prefix works.
+pub fn scan_channel_header(body: &[u8], mut visit: impl FnMut(&[u8], &str)) {
+    let mut reader = Reader::from_reader(body);
+    reader.trim_text(true);
+
+    let mut current = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(element)) => {
+                current = element.local_name().as_ref().to_vec();
+                if current == b"item" || current == b"entry" {
+                    break;
+                }
+            }
+            Ok(Event::Text(text)) => {
+                if let Ok(text) = text.unescape() {
+                    visit(&current, text.trim());
+                }
+            }
+            Ok(Event::End(_)) => current.clear(),
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
 // Struct builder-related helpers
 
+fn parse_yes_no(text: &str) -> Option<bool> {
+    match text.to_lowercase().as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses `[[HH:]MM:]SS` durations into seconds, fractional seconds are dropped
+fn parse_itunes_duration(text: &str) -> Option<i32> {
+    if text.is_empty() || text.split(':').count() > 3 {
+        return None;
+    }
+    text.split(':').try_fold(0i32, |total, part| {
+        let part = part.split('.').next()?.parse::<u32>().ok()?;
+        total
+            .checked_mul(60)?
+            .checked_add(i32::try_from(part).ok()?)
+    })
+}
+
 fn tags_from_categories(categories: Vec<Category>) -> Option<String> {
     let tags = categories
         .clone()
@@ -173,3 +789,40 @@ fn tags_from_categories(categories: Vec<Category>) -> Option<String> {
 fn gen_uuid_from_existing_id(guid: String) -> Uuid {
     Uuid::new_v5(&Uuid::NAMESPACE_URL, guid.as_bytes())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_itunes_duration;
+
+    #[test]
+    fn parses_clock_durations() {
+        assert_eq!(parse_itunes_duration("1:02:03"), Some(3723));
+        assert_eq!(parse_itunes_duration("01:00:00"), Some(3600));
+        assert_eq!(parse_itunes_duration("45:30"), Some(2730));
+        assert_eq!(parse_itunes_duration("0:59"), Some(59));
+    }
+
+    #[test]
+    fn parses_plain_seconds() {
+        assert_eq!(parse_itunes_duration("3723"), Some(3723));
+        assert_eq!(parse_itunes_duration("0"), Some(0));
+        // minutes past 59 only make sense as plain seconds, but are accepted either way
+        assert_eq!(parse_itunes_duration("90:00"), Some(5400));
+    }
+
+    #[test]
+    fn drops_fractional_seconds() {
+        assert_eq!(parse_itunes_duration("90.75"), Some(90));
+        assert_eq!(parse_itunes_duration("1:30.5"), Some(90));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(parse_itunes_duration(""), None);
+        assert_eq!(parse_itunes_duration("1:2:3:4"), None);
+        assert_eq!(parse_itunes_duration("1::30"), None);
+        assert_eq!(parse_itunes_duration("-30"), None);
+        assert_eq!(parse_itunes_duration("an hour"), None);
+        assert_eq!(parse_itunes_duration("999999:00:00"), None);
+    }
+}
@@ -0,0 +1,100 @@
+// Adaptive polling intervals, derived from how often a channel publishes and what the publisher and HTTP cache advertise
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use feed_rs::model::Feed;
+
+use super::rss::scan_channel_header;
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60 * 2);
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_secs(60 * 15);
+pub const MAX_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+// nobody is listening, so only check in occasionally to keep the channel page from going stale
+pub const UNSUBSCRIBED_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+// How many times we poll within a channel's typical gap between releases
+const POLLS_PER_RELEASE: u32 = 6;
+// Number of recent releases used to estimate the publishing cadence
+pub const CADENCE_SAMPLE_SIZE: i64 = 10;
+
+pub struct PollContext {
+    /// Most recent publish dates of the channel's episodes, newest first
+    pub publish_dates: Vec<DateTime<Utc>>,
+    /// Minimum refresh period advertised by the feed itself
+    pub refresh_hint: Option<Duration>,
+    /// Remaining freshness of the cached HTTP response
+    pub cache_ttl: Option<Duration>,
+    pub subscribers: i64,
+}
+
+/// Interval until the next poll after a successful one
+pub fn poll_interval(ctx: &PollContext) -> Duration {
+    if ctx.subscribers == 0 {
+        return UNSUBSCRIBED_POLL_INTERVAL;
+    }
+
+    let interval = publish_cadence(&ctx.publish_dates)
+        .map(|cadence| cadence / POLLS_PER_RELEASE)
+        .unwrap_or(DEFAULT_POLL_INTERVAL);
+
+    // polling earlier than the publisher asks for or while the cache is still fresh is wasted work
+    let interval = [ctx.refresh_hint, ctx.cache_ttl]
+        .into_iter()
+        .flatten()
+        .fold(interval, Duration::max);
+
+    interval.clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+}
+
+/// Exponential backoff on top of the regular interval after consecutive failures
+pub fn backoff_interval(interval: Duration, failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.min(16));
+    // never poll a failing channel more often than a healthy one
+    interval
+        .saturating_mul(factor)
+        .min(MAX_POLL_INTERVAL)
+        .max(interval)
+}
+
+/// Median gap between recent releases, stretched out for channels that have gone quiet
+fn publish_cadence(publish_dates: &[DateTime<Utc>]) -> Option<Duration> {
+    let mut gaps = publish_dates
+        .windows(2)
+        .filter_map(|pair| (pair[0] - pair[1]).to_std().ok())
+        .collect::<Vec<Duration>>();
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort();
+    let median = gaps[gaps.len() / 2];
+
+    let since_last_release = (Utc::now() - publish_dates[0]).to_std().unwrap_or_default();
+    Some(median.max(since_last_release))
+}
+
+/// Refresh period from RSS `<ttl>` (minutes) or the syndication module's `sy:updatePeriod`/`sy:updateFrequency`
+pub fn refresh_hint(feed: &Feed, body: &[u8]) -> Option<Duration> {
+    feed.ttl
+        .map(|minutes| Duration::from_secs(u64::from(minutes) * 60))
+        .or_else(|| syndication_period(body))
+}
+
+fn syndication_period(body: &[u8]) -> Option<Duration> {
+    let (mut period, mut frequency) = (None, None);
+    scan_channel_header(body, |name, text| match name {
+        b"updatePeriod" => period = Some(text.to_lowercase()),
+        b"updateFrequency" => frequency = text.parse::<u32>().ok(),
+        _ => {}
+    });
+
+    let hours = match period?.as_str() {
+        "hourly" => 1,
+        "daily" => 24,
+        "weekly" => 24 * 7,
+        "monthly" => 24 * 30,
+        "yearly" => 24 * 365,
+        _ => return None,
+    };
+    Some(Duration::from_secs(hours * 60 * 60) / frequency.unwrap_or(1).max(1))
+}
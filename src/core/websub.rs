@@ -0,0 +1,89 @@
+// WebSub (formerly PubSubHubbub) subscriber side, see https://www.w3.org/TR/websub/
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use hmac::{digest::KeyInit, Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+// How long we ask hubs to keep a subscription alive, hubs are free to pick another value
+pub const DEFAULT_LEASE_SECONDS: i64 = 60 * 60 * 24 * 10;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct WebSubSubscription {
+    pub channel_id: Uuid,
+    pub hub: String,
+    pub topic: String,
+    pub secret: String,
+    pub verified: bool,
+    /// The hub refused the subscription, the channel is only polled
+    pub denied: bool,
+}
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    hex::encode(secret)
+}
+
+pub fn callback_url(public_url: &str, channel_id: Uuid) -> String {
+    format!("{}/websub/{channel_id}", public_url.trim_end_matches('/'))
+}
+
+/// Sends a subscription request to the hub, which then verifies our intent asynchronously
+pub async fn request_subscription(subscription: &WebSubSubscription, callback: &str) -> Result<()> {
+    let lease_seconds = DEFAULT_LEASE_SECONDS.to_string();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let response = client
+        .post(&subscription.hub)
+        .form(&[
+            ("hub.callback", callback),
+            ("hub.mode", "subscribe"),
+            ("hub.topic", subscription.topic.as_str()),
+            ("hub.secret", subscription.secret.as_str()),
+            ("hub.lease_seconds", lease_seconds.as_str()),
+        ])
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "hub {} rejected subscription with status {}",
+            subscription.hub,
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+/// Checks an `X-Hub-Signature` header (`method=hexdigest`) against the body of a content distribution
+pub fn verify_signature(secret: &str, signature_header: &str, body: &[u8]) -> bool {
+    let Some((method, signature)) = signature_header.split_once('=') else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature.trim()) else {
+        return false;
+    };
+    let secret = secret.as_bytes();
+    match method.trim().to_ascii_lowercase().as_str() {
+        "sha1" => verify_hmac::<Hmac<Sha1>>(secret, body, &signature),
+        "sha256" => verify_hmac::<Hmac<Sha256>>(secret, body, &signature),
+        "sha384" => verify_hmac::<Hmac<Sha384>>(secret, body, &signature),
+        "sha512" => verify_hmac::<Hmac<Sha512>>(secret, body, &signature),
+        _ => false,
+    }
+}
+
+fn verify_hmac<M: Mac + KeyInit>(secret: &[u8], body: &[u8], signature: &[u8]) -> bool {
+    let Ok(mut mac) = <M as KeyInit>::new_from_slice(secret) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(signature).is_ok()
+}
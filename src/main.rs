@@ -6,34 +6,57 @@ mod services;
 
 use crate::core::user::User;
 use crate::routes::build_router;
+use crate::services::{download, history, scheduler, websub};
 use anyhow::{Context, Result};
 use async_redis_session::RedisSessionStore;
 use axum_login::axum_sessions::SessionLayer;
 use axum_login::{AuthLayer, PostgresStore};
-use config::{get_app_uri, init_context};
+use config::{get_app_uri, init_context, AppContext};
 
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::Mutex;
 use tokio_cron_scheduler::{Job, JobScheduler};
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
+use tracing::{info, warn};
 
 use tracing_subscriber;
 
-async fn start_fetch_feed_job() -> Result<()> {
-    // generate feed job every 2 hrs
+async fn start_fetch_feed_job(state: AppContext) -> Result<()> {
+    // every minute, poll the channels whose adaptive interval has run out
     let sched = JobScheduler::new().await?;
+    let running = Arc::new(Mutex::new(()));
     sched
         .add(Job::new_repeated_async(
-            Duration::from_secs(60 * 60 * 2),
-            |_, _| {
+            Duration::from_secs(60),
+            move |_, _| {
+                let state = state.clone();
+                let running = running.clone();
                 Box::pin(async move {
-                    let client = reqwest::Client::new();
-                    let res = client
-                        .put(format!("http://{}/feed/refresh", get_app_uri()))
-                        .send()
-                        .await;
-                    println!("Periodic Sync status: {:#?}", res);
+                    // a slow run shouldn't overlap with the next tick
+                    let Ok(_guard) = running.try_lock() else {
+                        return;
+                    };
+                    let res = scheduler::poll_due_channels(
+                        &state.redis_manager,
+                        &state.pool,
+                        &state.config,
+                        &state.fetch_limiter,
+                    )
+                    .await;
+                    match res {
+                        Ok(summary) if summary.channels.is_empty() => {}
+                        Ok(summary) => info!(
+                            "Periodic Sync: {} fetched, {} not modified, {} failed, {} new episodes",
+                            summary.fetched,
+                            summary.not_modified,
+                            summary.failed,
+                            summary.new_episodes
+                        ),
+                        Err(err) => warn!("Periodic Sync failed: {err:#}"),
+                    }
                 })
             },
         )?)
@@ -44,13 +67,92 @@ async fn start_fetch_feed_job() -> Result<()> {
         .context("could not start feed generation job")
 }
 
+async fn start_websub_renewal_job(state: AppContext) -> Result<()> {
+    // check for expiring WebSub leases every hour
+    let sched = JobScheduler::new().await?;
+    sched
+        .add(Job::new_repeated_async(
+            Duration::from_secs(60 * 60),
+            move |_, _| {
+                let state = state.clone();
+                Box::pin(async move {
+                    if let Err(err) =
+                        websub::renew_expiring_subscriptions(&state.config, &state.pool).await
+                    {
+                        warn!("WebSub renewal failed: {err:#}");
+                    }
+                })
+            },
+        )?)
+        .await?;
+    sched
+        .start()
+        .await
+        .context("could not start websub renewal job")
+}
+
+async fn start_download_job(state: AppContext) -> Result<()> {
+    // every minute, fetch episodes picked by download rules or requested by hand
+    let sched = JobScheduler::new().await?;
+    let running = Arc::new(Mutex::new(()));
+    sched
+        .add(Job::new_repeated_async(
+            Duration::from_secs(60),
+            move |_, _| {
+                let state = state.clone();
+                let running = running.clone();
+                Box::pin(async move {
+                    // downloads can outlast a tick, the next run picks up where this one ends
+                    let Ok(_guard) = running.try_lock() else {
+                        return;
+                    };
+                    match download::run_downloads(&state.config, &state.pool).await {
+                        Ok(summary)
+                            if summary.completed + summary.failed + summary.evicted == 0 => {}
+                        Ok(summary) => info!(
+                            "Downloads: {} completed, {} failed, {} evicted",
+                            summary.completed, summary.failed, summary.evicted
+                        ),
+                        Err(err) => warn!("Downloads failed: {err:#}"),
+                    }
+                })
+            },
+        )?)
+        .await?;
+    sched.start().await.context("could not start download job")
+}
+
+async fn start_history_job(state: AppContext) -> Result<()> {
+    // every hour, mark in-progress episodes nobody returned to as abandoned
+    let sched = JobScheduler::new().await?;
+    sched
+        .add(Job::new_repeated_async(
+            Duration::from_secs(60 * 60),
+            move |_, _| {
+                let state = state.clone();
+                Box::pin(async move {
+                    match history::abandon_stale_entries(&state.pool).await {
+                        Ok(0) => {}
+                        Ok(abandoned) => info!("History: {abandoned} episodes abandoned"),
+                        Err(err) => warn!("History cleanup failed: {err:#}"),
+                    }
+                })
+            },
+        )?)
+        .await?;
+    sched.start().await.context("could not start history job")
+}
+
 async fn start_server() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let state = init_context().await;
     let app_url = get_app_uri();
 
-    start_fetch_feed_job().await?;
+    start_fetch_feed_job(state.clone()).await?;
+    start_websub_renewal_job(state.clone()).await?;
+    start_download_job(state.clone()).await?;
+    start_history_job(state.clone()).await?;
 
     /* let mut secret = [0; 64];
     rand::thread_rng().fill(&mut secret); */
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect},
+    Extension, Json,
+};
+use http::{header, StatusCode};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::{
+        bookmark::{build_export, share_target, ExportFormat},
+        user::User,
+    },
+    error::ApiError,
+    services::{bookmark, feed},
+};
+
+use super::models::PaginationParams;
+
+#[derive(Deserialize)]
+pub struct NewBookmark {
+    /// Seconds into the episode
+    position: f64,
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BookmarkUpdate {
+    position: Option<f64>,
+    /// An empty string removes the note
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+fn validate_position(position: f64) -> Result<(), ApiError> {
+    if !position.is_finite() || position < 0.0 {
+        return Err(ApiError::new(
+            "position must be positive seconds",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    Ok(())
+}
+
+async fn require_episode(
+    episode_id: Uuid,
+    user: &User,
+    state: &AppContext,
+) -> Result<(), ApiError> {
+    if feed::get_episode(episode_id, user.id, &state.pool)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::new("episode not found", StatusCode::NOT_FOUND));
+    }
+    Ok(())
+}
+
+pub async fn star_episode(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_episode(id, &user, &state).await?;
+    bookmark::star_episode(user.id, id, &state.pool).await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn unstar_episode(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let res = bookmark::unstar_episode(user.id, id, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new("episode not starred", StatusCode::NOT_FOUND));
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_starred(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let episodes = bookmark::get_starred_episodes(user.id, &state.pool).await?;
+    Ok(Json(episodes))
+}
+
+pub async fn get_bookmarks(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    Query(params): Query<PaginationParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (offset, limit) = (params.offset.unwrap_or(0), params.limit.unwrap_or(50));
+    let bookmarks = bookmark::get_bookmarks(
+        user.id,
+        None,
+        offset,
+        Some(limit),
+        &state.config.public_url,
+        &state.pool,
+    )
+    .await?;
+    Ok(Json(bookmarks))
+}
+
+pub async fn get_episode_bookmarks(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let bookmarks = bookmark::get_bookmarks(
+        user.id,
+        Some(id),
+        0,
+        None,
+        &state.config.public_url,
+        &state.pool,
+    )
+    .await?;
+    Ok(Json(bookmarks))
+}
+
+pub async fn add_bookmark(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Json(input): Json<NewBookmark>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_position(input.position)?;
+    require_episode(id, &user, &state).await?;
+    let note = input.note.as_deref().filter(|note| !note.is_empty());
+    let bookmark_id =
+        bookmark::create_bookmark(user.id, id, input.position, note, &state.pool).await?;
+    let bookmark =
+        bookmark::get_bookmark(user.id, bookmark_id, &state.config.public_url, &state.pool).await?;
+    Ok((StatusCode::CREATED, Json(bookmark)))
+}
+
+pub async fn get_bookmark(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let bookmark = bookmark::get_bookmark(user.id, id, &state.config.public_url, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("bookmark not found", StatusCode::NOT_FOUND))?;
+    Ok(Json(bookmark))
+}
+
+pub async fn update_bookmark(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Json(update): Json<BookmarkUpdate>,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(position) = update.position {
+        validate_position(position)?;
+    }
+    let res = bookmark::update_bookmark(
+        user.id,
+        id,
+        update.position,
+        update.note.as_deref(),
+        &state.pool,
+    )
+    .await?;
+    if !res {
+        return Err(ApiError::new("bookmark not found", StatusCode::NOT_FOUND));
+    }
+    let bookmark =
+        bookmark::get_bookmark(user.id, id, &state.config.public_url, &state.pool).await?;
+    Ok(Json(bookmark))
+}
+
+pub async fn delete_bookmark(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let res = bookmark::delete_bookmark(user.id, id, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new("bookmark not found", StatusCode::NOT_FOUND));
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Starred episodes and all bookmarks as a Markdown (default) or CSV download
+pub async fn export_bookmarks(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    Query(params): Query<ExportParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let starred = bookmark::get_starred_episodes(user.id, &state.pool).await?;
+    let mut bookmarks = bookmark::get_bookmarks(
+        user.id,
+        None,
+        0,
+        None,
+        &state.config.public_url,
+        &state.pool,
+    )
+    .await?;
+    // group by episode, in the order they were first bookmarked
+    bookmarks.reverse();
+    let mut first_seen = HashMap::new();
+    for bookmark in &bookmarks {
+        let rank = first_seen.len();
+        first_seen.entry(bookmark.episode_id).or_insert(rank);
+    }
+    bookmarks.sort_by(|a, b| {
+        first_seen[&a.episode_id]
+            .cmp(&first_seen[&b.episode_id])
+            .then(a.position.total_cmp(&b.position))
+    });
+
+    let format = params.format;
+    let document = build_export(
+        format,
+        &format!("{} bookmarks", user.name),
+        &starred,
+        &bookmarks,
+    );
+    let disposition = format!(
+        "attachment; filename=\"librepod-bookmarks.{}\"",
+        format.extension()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        document,
+    ))
+}
+
+/// Public target of a bookmark's share link, redirects to the episode's audio at the bookmark
+pub async fn open_shared_bookmark(
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (audio_link, position) = bookmark::get_shared_bookmark(id, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("bookmark not found", StatusCode::NOT_FOUND))?;
+    Ok(Redirect::temporary(&share_target(&audio_link, position)))
+}
@@ -1,19 +1,26 @@
-use anyhow::anyhow;
 use axum::{
     extract::{Path, State},
     response::IntoResponse,
     Extension, Json,
 };
-use http::StatusCode;
+use http::{header, StatusCode};
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
     config::AppContext,
-    core::{rss::get_rss_data, user::User},
+    core::{
+        discovery::{resolve_feed, FeedResolution},
+        opml::{build_opml, parse_opml},
+        rss::get_uncached_rss_data,
+        user::User,
+    },
     error::ApiError,
     services::channel,
     services::feed,
+    services::image,
+    services::scheduler,
+    services::websub,
 };
 
 use serde::Deserialize;
@@ -23,6 +30,13 @@ pub struct AddChannel {
     rss_link: String,
 }
 
+#[derive(Deserialize)]
+pub struct SubscriptionUpdate {
+    /// From -2 (lowest) to 2 (highest)
+    priority: Option<i32>,
+    settings: Option<channel::SettingsUpdate>,
+}
+
 pub async fn get_subscriptions(
     Extension(user): Extension<User>,
     State(state): State<AppContext>,
@@ -32,7 +46,7 @@ pub async fn get_subscriptions(
 }
 
 pub async fn get_subscription(
-    Extension(_user): Extension<User>,
+    Extension(user): Extension<User>,
     Path(id): Path<Uuid>,
     State(state): State<AppContext>,
 ) -> Result<impl IntoResponse, ApiError> {
@@ -40,33 +54,158 @@ pub async fn get_subscription(
     if channel.is_none() {
         return Err(ApiError::new("channel not found", StatusCode::NOT_FOUND));
     }
-    let episodes = feed::get_channel_episodes(id, &state.pool).await?;
+    let episodes = feed::get_channel_episodes(id, user.id, &state.pool).await?;
+    let health = scheduler::get_channel_health(id, &state.pool).await?;
     Ok(Json(json!({
         "channel": channel,
-        "episodes": episodes
+        "episodes": episodes,
+        "health": health
     })))
 }
 
+pub async fn update_subscription(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Json(update): Json<SubscriptionUpdate>,
+) -> Result<impl IntoResponse, ApiError> {
+    if update
+        .priority
+        .is_some_and(|priority| !(-2..=2).contains(&priority))
+    {
+        return Err(ApiError::new(
+            "priority must be between -2 and 2",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    if let Some(settings) = &update.settings {
+        settings
+            .validate()
+            .map_err(|err| ApiError::new(err, StatusCode::BAD_REQUEST))?;
+    }
+    if !channel::is_subscribed(user.id, id, &state.pool).await? {
+        return Err(ApiError::new(
+            "subscription not found",
+            StatusCode::NOT_FOUND,
+        ));
+    }
+    if let Some(priority) = update.priority {
+        channel::set_subscription_priority(user.id, id, priority, &state.pool).await?;
+    }
+    if let Some(settings) = &update.settings {
+        channel::update_subscription_settings(user.id, id, settings, &state.pool).await?;
+    }
+    Ok(StatusCode::OK)
+}
+
 pub async fn add_subscription(
     Extension(user): Extension<User>,
     State(mut state): State<AppContext>,
     Json(input): Json<AddChannel>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let data = get_rss_data(&input.rss_link, &mut state.redis_manager)
-        .await
-        .ok_or(anyhow!("could not fetch feed"))?;
+    let resolution = resolve_feed(
+        &input.rss_link,
+        &state.config,
+        &state.fetch_limiter,
+        &mut state.redis_manager,
+    )
+    .await
+    .map_err(|err| {
+        ApiError::new(
+            &format!("could not fetch feed: {err}"),
+            StatusCode::BAD_REQUEST,
+        )
+    })?;
+    let mut data = match resolution {
+        FeedResolution::Resolved(data) => *data,
+        FeedResolution::Cached(link) => {
+            // fetched before, the refresh job keeps the stored channel up to date
+            if let Some(channel) = channel::get_channel_by_rss_link(&link, &state.pool).await? {
+                if !channel::is_subscribed(user.id, channel.id, &state.pool).await? {
+                    channel::add_subscription(user.id, channel.id, &state.pool).await?;
+                }
+                return Ok(Json(channel).into_response());
+            }
+            // the feed moved since, or was fetched without being stored
+            let _permit = state.fetch_limiter.acquire(&link).await;
+            get_uncached_rss_data(&link, &mut state.redis_manager)
+                .await
+                .into_data()
+                .map_err(|err| {
+                    ApiError::new(
+                        &format!("could not fetch feed: {err}"),
+                        StatusCode::BAD_REQUEST,
+                    )
+                })?
+        }
+        FeedResolution::Candidates(candidates) => {
+            return Ok((
+                StatusCode::MULTIPLE_CHOICES,
+                Json(json!({ "candidates": candidates })),
+            )
+                .into_response());
+        }
+    };
 
-    if (channel::get_channel(data.channel.id, &state.pool).await?).is_none() {
-        channel::add_channel(&data.channel, &state.pool).await?;
+    let channel_id = channel::store_channel(&mut data, &state.pool).await?;
+    if !channel::is_subscribed(user.id, channel_id, &state.pool).await? {
+        channel::add_subscription(user.id, channel_id, &state.pool).await?;
     }
 
-    channel::add_subscription(user.id, data.channel.id, &state.pool).await?;
-
     // also import missing episodes since you already took the time to fetch RSS
     // side effect that delays result, find alternative
-    feed::delta_update_feed(&state.pool, &data).await?;
+    feed::delta_update_feed(&state.pool, &data, state.config.mark_removed_episodes).await?;
+    websub::ensure_subscription(channel_id, data.websub.as_ref(), &state.config, &state.pool).await;
+
+    let channel = data.channel.clone();
+    image::spawn_artwork_sync(
+        data,
+        state.config.clone(),
+        state.fetch_limiter.clone(),
+        state.pool.clone(),
+    );
+    Ok(Json(channel).into_response())
+}
 
-    Ok(Json(data.channel))
+pub async fn import_subscriptions(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    body: String,
+) -> Result<impl IntoResponse, ApiError> {
+    let outlines = parse_opml(body.as_bytes())
+        .map_err(|_| ApiError::new("invalid OPML document", StatusCode::BAD_REQUEST))?;
+    let report = channel::import_subscriptions(
+        user.id,
+        outlines,
+        &state.redis_manager,
+        &state.pool,
+        &state.config,
+        &state.fetch_limiter,
+    )
+    .await;
+    Ok(Json(report))
+}
+
+pub async fn export_subscriptions(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let channels: Vec<_> = channel::get_subscriptions(&state.pool, user.id)
+        .await?
+        .into_iter()
+        .map(|subscription| subscription.channel)
+        .collect();
+    let document = build_opml(&format!("{} subscriptions", user.name), &channels)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/x-opml; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"librepod.opml\"",
+            ),
+        ],
+        document,
+    ))
 }
 
 pub async fn delete_subscription(
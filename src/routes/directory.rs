@@ -0,0 +1,94 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+
+use crate::{
+    config::AppContext,
+    core::{directory::DirectoryClient, user::User},
+    error::ApiError,
+    services::channel,
+};
+
+#[derive(Deserialize)]
+pub struct DirectorySearchParams {
+    q: String,
+}
+
+#[derive(Deserialize)]
+pub struct TrendingParams {
+    max: Option<u32>,
+    lang: Option<String>,
+    cat: Option<String>,
+}
+
+fn directory_client(state: &AppContext) -> Result<DirectoryClient<'_>, ApiError> {
+    DirectoryClient::from_config(&state.config).ok_or_else(|| {
+        ApiError::new(
+            "podcast directory is not configured",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+    })
+}
+
+fn directory_error(err: anyhow::Error) -> ApiError {
+    ApiError::new(
+        &format!("directory request failed: {err}"),
+        StatusCode::BAD_GATEWAY,
+    )
+}
+
+pub async fn search_directory(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    Query(params): Query<DirectorySearchParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut redis = state.redis_manager.clone();
+    let mut feeds = directory_client(&state)?
+        .search(&params.q, &mut redis)
+        .await
+        .map_err(directory_error)?;
+    channel::match_directory_feeds(user.id, &mut feeds, &state.pool).await?;
+    Ok(Json(feeds))
+}
+
+pub async fn trending_directory(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    Query(params): Query<TrendingParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut redis = state.redis_manager.clone();
+    let mut feeds = directory_client(&state)?
+        .trending(
+            params.max,
+            params.lang.as_deref(),
+            params.cat.as_deref(),
+            &mut redis,
+        )
+        .await
+        .map_err(directory_error)?;
+    channel::match_directory_feeds(user.id, &mut feeds, &state.pool).await?;
+    Ok(Json(feeds))
+}
+
+pub async fn get_directory_feed(
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut redis = state.redis_manager.clone();
+    let feed = directory_client(&state)?
+        .feed_by_id(id, &mut redis)
+        .await
+        .map_err(directory_error)?;
+    let Some(feed) = feed else {
+        return Err(ApiError::new("feed not found", StatusCode::NOT_FOUND));
+    };
+    let mut feeds = [feed];
+    channel::match_directory_feeds(user.id, &mut feeds, &state.pool).await?;
+    let [feed] = feeds;
+    Ok(Json(feed))
+}
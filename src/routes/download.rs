@@ -0,0 +1,89 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::user::User,
+    error::ApiError,
+    services::{download, feed},
+};
+
+#[derive(Deserialize)]
+pub struct DownloadRule {
+    /// Newest episodes to keep downloaded, `null` turns this rule off
+    keep_latest: Option<i32>,
+    /// Download the channel's episodes while they are queued
+    #[serde(default)]
+    queued: bool,
+}
+
+pub async fn get_downloads(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let overview = download::get_user_downloads(user.id, &state.config, &state.pool).await?;
+    Ok(Json(overview))
+}
+
+/// Queues the episode, the download job picks it up within a minute
+pub async fn add_download(
+    Extension(user): Extension<User>,
+    Path(episode_id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    if feed::get_episode(episode_id, user.id, &state.pool)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::new("episode not found", StatusCode::NOT_FOUND));
+    }
+    download::request_download(user.id, episode_id, &state.pool).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn delete_download(
+    Extension(user): Extension<User>,
+    Path(episode_id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let res = download::delete_user_download(user.id, episode_id, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new("download not found", StatusCode::NOT_FOUND));
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn set_download_rule(
+    Extension(user): Extension<User>,
+    Path(channel_id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Json(rule): Json<DownloadRule>,
+) -> Result<impl IntoResponse, ApiError> {
+    if rule.keep_latest.is_some_and(|keep| keep < 1) {
+        return Err(ApiError::new(
+            "keep_latest must be at least 1",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let res = download::set_download_rule(
+        user.id,
+        channel_id,
+        rule.keep_latest,
+        rule.queued,
+        &state.pool,
+    )
+    .await?;
+    if !res {
+        return Err(ApiError::new(
+            "subscription not found",
+            StatusCode::NOT_FOUND,
+        ));
+    }
+    Ok(StatusCode::OK)
+}
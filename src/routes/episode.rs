@@ -0,0 +1,111 @@
+use axum::{
+    body::{boxed, Body, StreamBody},
+    extract::{Path, State},
+    headers,
+    response::{IntoResponse, Response},
+    Extension, Json, TypedHeader,
+};
+use http::{header, Request, StatusCode};
+use serde::Deserialize;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::{audio::stream_audio, download::file_path, user::User},
+    error::ApiError,
+    services::{download, feed, playback},
+};
+
+#[derive(Deserialize)]
+pub struct PositionUpdate {
+    /// Seconds into the episode
+    position: f64,
+    duration: Option<f64>,
+    /// Defaults to the User-Agent
+    device: Option<String>,
+}
+
+/// Proxies the episode's enclosure, a stable URL that keeps the listener's IP away from the host.
+/// Episodes downloaded to the server are served from disk instead.
+pub async fn stream_episode_audio(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    request: Request<Body>,
+) -> Result<Response, ApiError> {
+    let episode = feed::get_episode(id, user.id, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("episode not found", StatusCode::NOT_FOUND))?;
+
+    if let Some(mime_type) = download::get_completed_download(id, &state.pool).await? {
+        let path = file_path(&state.config.download_storage_path, id);
+        let mime = mime_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        // ServeFile takes care of Range, If-Range and conditional requests
+        let response = ServeFile::new_with_mime(path, &mime)
+            .oneshot(request)
+            .await
+            .map_err(anyhow::Error::from)?;
+        return Ok(response.map(boxed));
+    }
+
+    let headers = request.headers();
+    let audio = stream_audio(
+        &episode.audio_link,
+        headers.get(header::RANGE),
+        headers.get(header::IF_RANGE),
+    )
+    .await
+    .map_err(|err| {
+        warn!("Could not stream {}: {err}", episode.audio_link);
+        ApiError::new(
+            "could not fetch audio from publisher",
+            StatusCode::BAD_GATEWAY,
+        )
+    })?;
+    Ok((audio.status, audio.headers, StreamBody::new(audio.body)).into_response())
+}
+
+pub async fn get_playback_position(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let position = playback::get_position(user.id, id, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("no playback position", StatusCode::NOT_FOUND))?;
+    Ok(Json(position))
+}
+
+pub async fn set_playback_position(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    Json(update): Json<PositionUpdate>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !playback::is_valid_report(update.position, update.duration) {
+        return Err(ApiError::new(
+            "position and duration must be positive seconds",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let device = update
+        .device
+        .or_else(|| user_agent.map(|TypedHeader(user_agent)| user_agent.to_string()));
+    let res = playback::save_position(
+        user.id,
+        id,
+        update.position,
+        update.duration,
+        device.as_deref(),
+        &state.pool,
+    )
+    .await?;
+    if !res {
+        return Err(ApiError::new("episode not found", StatusCode::NOT_FOUND));
+    }
+    Ok(StatusCode::OK)
+}
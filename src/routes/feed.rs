@@ -3,39 +3,229 @@ use axum::{
     response::IntoResponse,
     Extension, Json,
 };
-use http::StatusCode;
+use chrono::NaiveDate;
+use http::{header, StatusCode};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
 use uuid::Uuid;
 
-use crate::{config::AppContext, core::user::User, error::ApiError, services::feed};
+use crate::{
+    config::AppContext,
+    core::{cache::get_file_with_cache, user::User},
+    error::ApiError,
+    services::{
+        channel,
+        feed::{self, FeedSort, FeedView},
+        podcast,
+    },
+};
+
+// keeps custom views, which are loaded whole to shuffle them, to a sensible size
+const MAX_VIEW_DAYS: i64 = 366;
 
-use super::models::PaginationParams;
+#[derive(Deserialize)]
+pub struct FeedParams {
+    offset: Option<i64>,
+    limit: Option<i64>,
+    #[serde(default)]
+    sort: FeedSort,
+    /// IANA timezone days are counted in when sorting by priority, UTC by default
+    tz: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ViewParams {
+    /// IANA timezone the view's days are counted in, UTC by default
+    tz: Option<String>,
+    /// First and last day of a custom view
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    /// Leave out played episodes, on by default
+    unplayed: Option<bool>,
+    #[serde(default)]
+    sort: FeedSort,
+    /// With `sort=priority`, higher priority subscriptions tend to come first
+    #[serde(default)]
+    shuffle: bool,
+    /// Repeats an earlier shuffle, implies `shuffle`
+    seed: Option<u64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct TranscriptParams {
+    language: Option<String>,
+}
 
 pub async fn get_episode(
-    Extension(_user): Extension<User>,
+    Extension(user): Extension<User>,
     Path(id): Path<Uuid>,
     State(state): State<AppContext>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let episode = feed::get_episode(id, &state.pool).await?;
+    let episode = podcast::get_episode_details(id, user.id, &state.pool).await?;
     if episode.is_none() {
         return Err(ApiError::new("episode not found", StatusCode::NOT_FOUND));
     }
     Ok(Json(episode))
 }
 
+/// Serves the episode's transcript, preferring the requested language when several are listed
+pub async fn get_transcript(
+    Extension(_user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(mut state): State<AppContext>,
+    Query(params): Query<TranscriptParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let transcripts = podcast::get_transcripts(id, &state.pool).await?;
+    let transcript = params
+        .language
+        .and_then(|language| {
+            transcripts.iter().find(|t| {
+                t.language
+                    .as_ref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(&language))
+            })
+        })
+        .or(transcripts.first())
+        .ok_or_else(|| ApiError::new("transcript not found", StatusCode::NOT_FOUND))?;
+
+    let body = fetch_episode_file(&transcript.url, &mut state).await?;
+    Ok(([(header::CONTENT_TYPE, transcript.mime_type.clone())], body))
+}
+
+pub async fn get_chapters(
+    Extension(_user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(mut state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let chapters = podcast::get_chapters(id, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("chapters not found", StatusCode::NOT_FOUND))?;
+
+    let body = fetch_episode_file(&chapters.url, &mut state).await?;
+    Ok(([(header::CONTENT_TYPE, chapters.mime_type)], body))
+}
+
+async fn fetch_episode_file(url: &str, state: &mut AppContext) -> Result<Vec<u8>, ApiError> {
+    let unavailable = || {
+        ApiError::new(
+            "could not fetch file from publisher",
+            StatusCode::BAD_GATEWAY,
+        )
+    };
+    let response = get_file_with_cache(url, &mut state.redis_manager)
+        .await
+        .map_err(|_| unavailable())?;
+    if !response.status.is_success() {
+        return Err(unavailable());
+    }
+    Ok(response.body)
+}
+
 pub async fn retrieve_feed(
     Extension(user): Extension<User>,
     State(state): State<AppContext>,
-    Query(params): Query<PaginationParams>,
+    Query(params): Query<FeedParams>,
 ) -> Result<impl IntoResponse, ApiError> {
     let (offset, limit) = (params.offset.unwrap_or(0), params.limit.unwrap_or(15));
-    let episodes = feed::get_subscription_episodes(user.id, &state.pool, offset, limit).await?;
+    let timezone = params.tz.as_deref().unwrap_or("UTC");
+    if !feed::is_known_timezone(timezone, &state.pool).await? {
+        return Err(ApiError::new("unknown timezone", StatusCode::BAD_REQUEST));
+    }
+    let episodes =
+        feed::get_subscription_episodes(user.id, &state.pool, offset, limit, params.sort, timezone)
+            .await?;
     Ok(Json(episodes))
 }
 
 pub async fn refresh_feed(
     Extension(_user): Extension<User>,
-    State(mut state): State<AppContext>,
+    State(state): State<AppContext>,
 ) -> Result<impl IntoResponse, ApiError> {
-    feed::update_all_feeds(&mut state.redis_manager, &state.pool).await?;
-    Ok(StatusCode::OK)
+    let summary = feed::update_all_feeds(
+        &state.redis_manager,
+        &state.pool,
+        &state.config,
+        &state.fetch_limiter,
+    )
+    .await?;
+    Ok(Json(summary))
+}
+
+/// Episodes of a date range such as today or this week, ready to be played in order or shuffled.
+/// Shuffled views return their seed, which pages through or replays the same order.
+pub async fn get_feed_view(
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+    State(state): State<AppContext>,
+    Query(params): Query<ViewParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let view = match (name.as_str(), params.from, params.to) {
+        ("today", ..) => FeedView::Today,
+        ("week", ..) => FeedView::Week,
+        ("month", ..) => FeedView::Month,
+        ("custom", Some(from), Some(to)) => {
+            let days = (to - from).num_days();
+            if !(0..MAX_VIEW_DAYS).contains(&days) {
+                return Err(ApiError::new(
+                    "from must be before to and within a year of it",
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            FeedView::Custom { from, to }
+        }
+        ("custom", ..) => {
+            return Err(ApiError::new(
+                "custom views need from and to dates",
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+        _ => return Err(ApiError::new("view not found", StatusCode::NOT_FOUND)),
+    };
+    let timezone = params.tz.as_deref().unwrap_or("UTC");
+    let (start, end) = feed::view_range(view, timezone, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("unknown timezone", StatusCode::BAD_REQUEST))?;
+
+    let unplayed = params.unplayed.unwrap_or(true);
+    let mut episodes = feed::get_view_episodes(
+        user.id,
+        (start, end),
+        unplayed,
+        params.sort,
+        timezone,
+        &state.pool,
+    )
+    .await?;
+    // seeds stay below 2^53 so JavaScript clients can pass them back unchanged
+    let seed = params.seed.or_else(|| {
+        params
+            .shuffle
+            .then(|| rand::thread_rng().gen_range(0..1 << 53))
+    });
+    match (seed, params.sort) {
+        (Some(seed), FeedSort::Priority) => {
+            let priorities = channel::get_subscription_priorities(user.id, &state.pool).await?;
+            feed::shuffle_episodes_by_priority(&mut episodes, seed, &priorities);
+        }
+        (Some(seed), FeedSort::Date) => feed::shuffle_episodes(&mut episodes, seed),
+        (None, _) => {}
+    }
+    let total = episodes.len();
+    let episodes: Vec<_> = episodes
+        .into_iter()
+        .skip(params.offset.unwrap_or(0))
+        .take(params.limit.unwrap_or(usize::MAX))
+        .collect();
+    Ok(Json(json!({
+        "view": name,
+        "timezone": timezone,
+        "from": start.timestamp_micros(),
+        "to": end.timestamp_micros(),
+        "seed": seed,
+        "total": total,
+        "episodes": episodes
+    })))
 }
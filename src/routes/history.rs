@@ -1,12 +1,26 @@
-use crate::{config::AppContext, core::user::User, error::ApiError, services::history};
+use crate::{
+    config::AppContext,
+    core::user::User,
+    error::ApiError,
+    services::history::{self, HistoryState},
+};
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
     Extension, Json,
 };
 use http::StatusCode;
+use serde::Deserialize;
 use uuid::Uuid;
 
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    offset: Option<i64>,
+    limit: Option<i64>,
+    state: Option<HistoryState>,
+    channel_id: Option<Uuid>,
+}
+
 pub async fn add_history(
     Extension(user): Extension<User>,
     Path(id): Path<Uuid>,
@@ -22,9 +36,19 @@ pub async fn add_history(
 pub async fn get_history(
     Extension(user): Extension<User>,
     State(state): State<AppContext>,
+    Query(params): Query<HistoryParams>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let episodes = history::get_history(user.id, &state.pool).await?;
-    Ok(Json(episodes))
+    let (offset, limit) = (params.offset.unwrap_or(0), params.limit.unwrap_or(20));
+    let entries = history::get_history(
+        user.id,
+        params.state,
+        params.channel_id,
+        offset,
+        limit,
+        &state.pool,
+    )
+    .await?;
+    Ok(Json(entries))
 }
 
 pub async fn clear_history(
@@ -0,0 +1,66 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension,
+};
+use http::{header, HeaderMap, StatusCode};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::{
+        image::{image_directory, variant_file_name, VARIANT_MIME_TYPE},
+        user::User,
+    },
+    error::ApiError,
+    services::image,
+};
+
+// the id stays the same when the publisher replaces the artwork, so clients revalidate daily
+const IMAGE_CACHE_CONTROL: &str = "private, max-age=86400";
+
+#[derive(Deserialize)]
+pub struct ImageParams {
+    /// Bounding box in pixels, rounded up to the closest variant; the original when omitted
+    size: Option<u32>,
+}
+
+pub async fn get_image(
+    Extension(_user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Query(params): Query<ImageParams>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let not_found = || ApiError::new("image not found", StatusCode::NOT_FOUND);
+    let image = image::get_image(id, &state.pool)
+        .await?
+        .ok_or_else(not_found)?;
+
+    let file_name = variant_file_name(params.size);
+    let etag = format!("\"{}-{file_name}\"", image.content_hash);
+    let mime_type = match params.size {
+        Some(_) => VARIANT_MIME_TYPE.to_string(),
+        None => image.mime_type,
+    };
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, IMAGE_CACHE_CONTROL.to_string()),
+    ];
+
+    let unchanged = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if unchanged {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    let path = image_directory(&state.config.image_storage_path, image.id).join(file_name);
+    let body = tokio::fs::read(path).await.map_err(|_| not_found())?;
+    Ok((cache_headers, [(header::CONTENT_TYPE, mime_type)], body).into_response())
+}
@@ -1,20 +1,40 @@
 mod auth;
+mod bookmark;
 mod channel;
+mod directory;
+mod download;
+mod episode;
 mod feed;
 mod history;
+mod image;
 mod models;
 mod player;
+mod playlist;
+mod queue;
+mod search;
+mod stats;
+mod websub;
 
 use self::auth::*;
+use self::bookmark::*;
 use self::channel::*;
+use self::directory::*;
+use self::download::*;
+use self::episode::*;
 use self::feed::*;
 use self::history::*;
+use self::image::*;
 use self::player::*;
+use self::playlist::*;
+use self::queue::*;
+use self::search::*;
+use self::stats::*;
+use self::websub::*;
 
 use crate::{config::AppContext, core::user::User};
 
 use axum::{
-    routing::{get, post, put},
+    routing::{delete, get, post, put},
     Router,
 };
 use axum_login::RequireAuthorizationLayer;
@@ -28,15 +48,76 @@ type RequireAuth = RequireAuthorizationLayer<Uuid, User>;
 pub fn build_router() -> Router<AppContext> {
     let channel_routes = Router::new()
         .route("/", get(get_subscriptions).post(add_subscription))
-        .route("/:id", get(get_subscription).delete(delete_subscription))
+        .route("/import", post(import_subscriptions))
+        .route("/export.opml", get(export_subscriptions))
+        .route(
+            "/:id",
+            get(get_subscription)
+                .patch(update_subscription)
+                .delete(delete_subscription),
+        )
+        .route("/:id/download-rule", put(set_download_rule))
         .route_layer(RequireAuth::login());
 
     let feed_routes = Router::new()
         .route("/", get(retrieve_feed))
         .route("/:id", get(get_episode))
+        .route("/:id/transcript", get(get_transcript))
+        .route("/:id/chapters", get(get_chapters))
+        .route("/views/:view", get(get_feed_view))
         .route("/refresh", put(refresh_feed))
         .route_layer(RequireAuth::login());
 
+    let episode_routes = Router::new()
+        .route("/:id/audio", get(stream_episode_audio))
+        .route(
+            "/:id/position",
+            get(get_playback_position).put(set_playback_position),
+        )
+        .route("/:id/star", put(star_episode).delete(unstar_episode))
+        .route(
+            "/:id/bookmarks",
+            get(get_episode_bookmarks).post(add_bookmark),
+        )
+        .route_layer(RequireAuth::login());
+
+    let download_routes = Router::new()
+        .route("/", get(get_downloads))
+        .route("/:id", post(add_download).delete(delete_download))
+        .route_layer(RequireAuth::login());
+
+    let bookmark_routes = Router::new()
+        .route("/", get(get_bookmarks))
+        .route("/export", get(export_bookmarks))
+        .route(
+            "/:id",
+            get(get_bookmark)
+                .patch(update_bookmark)
+                .delete(delete_bookmark),
+        )
+        .route_layer(RequireAuth::login());
+
+    let playlist_routes = Router::new()
+        .route("/", get(get_playlists).post(create_playlist))
+        .route(
+            "/:id",
+            get(get_playlist)
+                .patch(update_playlist)
+                .delete(delete_playlist),
+        )
+        .route(
+            "/:id/episode/:episode_id",
+            post(add_playlist_episode).delete(remove_playlist_episode),
+        )
+        .route("/:id/episode/:episode_id/move", put(move_playlist_episode))
+        .route(
+            "/:id/member",
+            get(get_playlist_members).post(share_playlist),
+        )
+        .route("/:id/member/:user_id", delete(unshare_playlist))
+        .route("/:id/edits", get(get_playlist_edits))
+        .route_layer(RequireAuth::login());
+
     let auth_routes = Router::new()
         .route("/logout", put(logout_user))
         .route_layer(RequireAuth::login())
@@ -47,8 +128,33 @@ pub fn build_router() -> Router<AppContext> {
         .route("/", get(get_history).delete(clear_history))
         .route("/:id", post(add_history));
 
+    let queue_routes = Router::new()
+        .route("/", get(get_queue).delete(clear_queue))
+        .route("/shuffle", post(shuffle_queue))
+        .route("/:id", post(append_to_queue).delete(remove_from_queue))
+        .route("/playlist/:id", post(queue_playlist))
+        .route("/:id/next", post(play_next))
+        .route("/:id/move", put(move_queue_item));
+
     let user_routes = Router::new()
         .nest("/history", history_routes)
+        .nest("/queue", queue_routes)
+        .route("/starred", get(get_starred))
+        .route("/stats", get(get_stats))
+        .route_layer(RequireAuth::login());
+
+    let search_routes = Router::new()
+        .route("/", get(search))
+        .route_layer(RequireAuth::login());
+
+    let directory_routes = Router::new()
+        .route("/search", get(search_directory))
+        .route("/trending", get(trending_directory))
+        .route("/feed/:id", get(get_directory_feed))
+        .route_layer(RequireAuth::login());
+
+    let image_routes = Router::new()
+        .route("/:id", get(get_image))
         .route_layer(RequireAuth::login());
 
     let player_routes = Router::new()
@@ -59,11 +165,26 @@ pub fn build_router() -> Router<AppContext> {
         )
         .route_layer(RequireAuth::login());
 
+    // opened by whoever a bookmark was shared with
+    let share_routes = Router::new().route("/bookmark/:id", get(open_shared_bookmark));
+
+    // called by WebSub hubs, so these can't sit behind a login
+    let websub_routes = Router::new().route("/:id", get(verify_intent).post(receive_content));
+
     Router::new()
         .route("/", get(|| async { "Hello, World!" }))
         .nest("/channel", channel_routes)
         .nest("/feed", feed_routes)
+        .nest("/episode", episode_routes)
+        .nest("/download", download_routes)
+        .nest("/bookmark", bookmark_routes)
+        .nest("/playlist", playlist_routes)
         .nest("/auth", auth_routes)
         .nest("/user", user_routes)
+        .nest("/search", search_routes)
+        .nest("/directory", directory_routes)
+        .nest("/image", image_routes)
         .nest("/player", player_routes)
+        .nest("/share", share_routes)
+        .nest("/websub", websub_routes)
 }
@@ -1,9 +1,17 @@
 // Module for any shared route-related structs or logic
 
 use serde::Deserialize;
+use uuid::Uuid;
 
 #[derive(Debug, Deserialize)]
 pub struct PaginationParams {
     pub offset: Option<i64>,
     pub limit: Option<i64>,
 }
+
+/// Where to move an item of an ordered list such as the queue or a playlist
+#[derive(Debug, Deserialize)]
+pub struct MoveParams {
+    /// Item to place it behind, `null` moves it to the front
+    pub after: Option<Uuid>,
+}
@@ -7,12 +7,13 @@ use axum::{
     Extension,
 };
 use axum::{headers, TypedHeader};
-use redis::aio::ConnectionManager;
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::ops::ControlFlow;
 
@@ -21,14 +22,50 @@ use axum::extract::connect_info::ConnectInfo;
 use axum::extract::ws::CloseFrame;
 
 // allows to split the websocket stream into separate TX and RX branches
-use futures::{sink::SinkExt, stream::StreamExt};
+use futures::{
+    sink::SinkExt,
+    stream::{SplitSink, StreamExt},
+};
 
-use crate::{config::AppContext, core::user::User};
+use crate::{
+    config::AppContext,
+    core::user::User,
+    services::{
+        channel::{self, SubscriptionSettings},
+        history, playback,
+    },
+};
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 struct PlayerState {
-    episode_id: String,
-    player_time: u64,
+    episode_id: Uuid,
+    /// Seconds into the episode
+    player_time: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
+    /// Settings of the episode's subscription, sent along with the state
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    settings: Option<SubscriptionSettings>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum PlayerAction {
+    GetSettings { episode_id: Uuid },
+}
+
+#[derive(Serialize, Debug)]
+struct EpisodeSettings {
+    episode_id: Uuid,
+    settings: Option<SubscriptionSettings>,
+}
+
+/// What the connection knows about an episode being played
+#[derive(Default)]
+struct EpisodeContext {
+    settings: Option<SubscriptionSettings>,
+    /// Last position reported over this connection
+    player_time: Option<f64>,
 }
 
 /// The handler for the HTTP request (this gets called when the HTTP GET lands at the start
@@ -40,7 +77,7 @@ pub async fn player_ws_handler(
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    State(mut state): State<AppContext>,
+    State(state): State<AppContext>,
     Extension(user): Extension<User>,
 ) -> impl IntoResponse {
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
@@ -51,9 +88,8 @@ pub async fn player_ws_handler(
     info!("`{user_agent}` at {addr} connected.");
     // finalize the upgrade process by returning upgrade callback.
     // we can customize the callback by sending additional info such as address.
-    let mut redis_connection = state.redis_manager;
     ws.on_upgrade(move |socket| async move {
-        handle_socket(socket, addr, &mut redis_connection, user.id).await
+        handle_socket(socket, addr, &state.pool, user.id, &user_agent).await
     })
 }
 
@@ -61,31 +97,98 @@ pub async fn player_ws_handler(
 async fn handle_socket(
     socket: WebSocket,
     who: SocketAddr,
-    con: &mut ConnectionManager,
+    pool: &PgPool,
     user_id: Uuid,
+    device: &str,
 ) {
     let (mut tx, mut rx) = socket.split();
+    let mut episodes: HashMap<Uuid, EpisodeContext> = HashMap::new();
     while let Some(Ok(msg)) = rx.next().await {
         if let Message::Text(text) = msg {
             // Decode our message and warn if it's something we don't know about
             if let Ok(player_state) = serde_json::from_str::<PlayerState>(&text) {
+                if !playback::is_valid_report(player_state.player_time, player_state.duration) {
+                    warn!("Ignoring invalid position {text} from User {user_id}");
+                    continue;
+                }
                 // Apply the state
                 info!("Storing {:#?} for User {user_id}", player_state);
-                let result = redis::cmd("SET")
-                    .arg(format!("User:{user_id}:PlayerState"))
-                    .arg(text)
-                    .query_async::<_, ()>(con)
-                    .await;
+                let result = playback::save_position(
+                    user_id,
+                    player_state.episode_id,
+                    player_state.player_time,
+                    player_state.duration,
+                    Some(device),
+                    pool,
+                )
+                .await;
                 info!("Result: {:#?}", result);
+
+                let context = match episodes.get_mut(&player_state.episode_id) {
+                    Some(context) => context,
+                    None => {
+                        let settings = load_settings(user_id, player_state.episode_id, pool).await;
+                        episodes
+                            .entry(player_state.episode_id)
+                            .or_insert(EpisodeContext {
+                                settings,
+                                player_time: None,
+                            })
+                    }
+                };
+                let previous_time = context.player_time.replace(player_state.player_time);
+                if reached_outro(&player_state, previous_time, context.settings.as_ref()) {
+                    info!(
+                        "Outro of {} reached, marking as played for User {user_id}",
+                        player_state.episode_id
+                    );
+                    if let Err(err) =
+                        history::mark_played(user_id, player_state.episode_id, pool).await
+                    {
+                        warn!(
+                            "Could not mark {} as played: {err}",
+                            player_state.episode_id
+                        );
+                    }
+                }
+            } else if let Ok(PlayerAction::GetSettings { episode_id }) =
+                serde_json::from_str::<PlayerAction>(&text)
+            {
+                let settings = load_settings(user_id, episode_id, pool).await;
+                episodes.entry(episode_id).or_default().settings = settings.clone();
+                let reply = EpisodeSettings {
+                    episode_id,
+                    settings,
+                };
+                info!("Episode settings: {:#?}", reply);
+                send_json(&mut tx, &reply, who).await;
             } else if text == "get_state" {
+                // resume whatever was played last, on any device
                 info!("Getting state for User {user_id}");
-                let result = redis::cmd("GET")
-                    .arg(format!("User:{user_id}:PlayerState"))
-                    .query_async::<_, String>(con)
-                    .await;
-                if let Ok(result) = result {
-                    let send_result = tx.send(Message::Text(result.clone())).await;
-                    info!("Redis result: {:#?}", result);
+                let result = playback::get_last_position(user_id, pool).await;
+                if let Ok(Some(position)) = result {
+                    let settings = load_settings(user_id, position.episode_id, pool).await;
+                    // start after the intro when it hasn't been listened past yet
+                    let skip_intro = settings
+                        .as_ref()
+                        .map_or(0.0, |settings| f64::from(settings.skip_intro));
+                    let player_time = if position.position < skip_intro
+                        && position
+                            .duration
+                            .is_none_or(|duration| skip_intro < duration)
+                    {
+                        skip_intro
+                    } else {
+                        position.position
+                    };
+                    let state = PlayerState {
+                        episode_id: position.episode_id,
+                        player_time,
+                        duration: position.duration,
+                        settings,
+                    };
+                    info!("Player state: {:#?}", state);
+                    send_json(&mut tx, &state, who).await;
                 }
             } else {
                 warn!("Unknown action received: {}", text);
@@ -95,3 +198,54 @@ async fn handle_socket(
     // returning from the handler closes the websocket connection
     info!("Websocket context {who} destroyed");
 }
+
+/// Failures are only logged, the client asks again when it reconnects
+async fn send_json(
+    tx: &mut SplitSink<WebSocket, Message>,
+    value: &impl Serialize,
+    who: SocketAddr,
+) {
+    let text = match serde_json::to_string(value) {
+        Ok(text) => text,
+        Err(err) => {
+            error!("Could not serialize message for {who}: {err}");
+            return;
+        }
+    };
+    if let Err(err) = tx.send(Message::Text(text)).await {
+        warn!("Could not send message to {who}: {err}");
+    }
+}
+
+async fn load_settings(
+    user_id: Uuid,
+    episode_id: Uuid,
+    pool: &PgPool,
+) -> Option<SubscriptionSettings> {
+    channel::get_episode_settings(user_id, episode_id, pool)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("Could not load settings of {episode_id} for User {user_id}: {err}");
+            None
+        })
+}
+
+/// Whether playback just moved into the part skipped at the end, which counts as finishing the
+/// episode. Needs the duration and an earlier position from this connection, so seeking around
+/// doesn't trigger it again.
+fn reached_outro(
+    state: &PlayerState,
+    previous_time: Option<f64>,
+    settings: Option<&SubscriptionSettings>,
+) -> bool {
+    let (Some(duration), Some(previous_time), Some(settings)) =
+        (state.duration, previous_time, settings)
+    else {
+        return false;
+    };
+    if settings.skip_outro == 0 {
+        return false;
+    }
+    let outro_start = duration - f64::from(settings.skip_outro);
+    previous_time < outro_start && state.player_time >= outro_start
+}
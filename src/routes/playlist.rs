@@ -0,0 +1,244 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::user::User,
+    error::ApiError,
+    services::{
+        feed,
+        playlist::{self, PlaylistRole},
+    },
+};
+
+use super::models::{MoveParams, PaginationParams};
+
+#[derive(Deserialize)]
+pub struct NewPlaylist {
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistUpdate {
+    name: Option<String>,
+    /// An empty string removes the description
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistInvite {
+    /// Name or email of the account to share with
+    user: String,
+    role: PlaylistRole,
+}
+
+/// 404 when the user can't see the playlist, 403 when they may not do this with it
+async fn require_role(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    required: PlaylistRole,
+    pool: &PgPool,
+) -> Result<PlaylistRole, ApiError> {
+    let role = playlist::get_role(playlist_id, user_id, pool)
+        .await?
+        .ok_or_else(|| ApiError::new("playlist not found", StatusCode::NOT_FOUND))?;
+    if role < required {
+        return Err(ApiError::new(
+            "not allowed to change this playlist",
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    Ok(role)
+}
+
+pub async fn get_playlists(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let playlists = playlist::get_playlists(user.id, &state.pool).await?;
+    Ok(Json(playlists))
+}
+
+pub async fn create_playlist(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    Json(input): Json<NewPlaylist>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = input.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::new(
+            "playlist name is empty",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let description = input.description.as_deref().filter(|d| !d.is_empty());
+    let id = playlist::create_playlist(user.id, name, description, &state.pool).await?;
+    let playlist = playlist::get_playlist(id, user.id, &state.pool).await?;
+    Ok((StatusCode::CREATED, Json(playlist)))
+}
+
+pub async fn get_playlist(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let playlist = playlist::get_playlist(id, user.id, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("playlist not found", StatusCode::NOT_FOUND))?;
+    let episodes = playlist::get_playlist_episodes(id, user.id, &state.pool).await?;
+    Ok(Json(json!({
+        "playlist": playlist,
+        "episodes": episodes
+    })))
+}
+
+pub async fn update_playlist(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Json(input): Json<PlaylistUpdate>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Editor, &state.pool).await?;
+    let name = input.name.as_deref().map(str::trim);
+    if name.is_some_and(str::is_empty) {
+        return Err(ApiError::new(
+            "playlist name is empty",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    playlist::update_playlist(id, user.id, name, input.description.as_deref(), &state.pool).await?;
+    let playlist = playlist::get_playlist(id, user.id, &state.pool).await?;
+    Ok(Json(playlist))
+}
+
+pub async fn delete_playlist(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Owner, &state.pool).await?;
+    playlist::delete_playlist(id, &state.pool).await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn add_playlist_episode(
+    Extension(user): Extension<User>,
+    Path((id, episode_id)): Path<(Uuid, Uuid)>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Editor, &state.pool).await?;
+    if feed::get_episode(episode_id, user.id, &state.pool)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::new("episode not found", StatusCode::NOT_FOUND));
+    }
+    let res = playlist::add_episode(id, user.id, episode_id, &state.pool).await?;
+    if !res {
+        return Ok(StatusCode::OK);
+    }
+    Ok(StatusCode::CREATED)
+}
+
+pub async fn remove_playlist_episode(
+    Extension(user): Extension<User>,
+    Path((id, episode_id)): Path<(Uuid, Uuid)>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Editor, &state.pool).await?;
+    let res = playlist::remove_episode(id, user.id, episode_id, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new(
+            "episode not in playlist",
+            StatusCode::NOT_FOUND,
+        ));
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn move_playlist_episode(
+    Extension(user): Extension<User>,
+    Path((id, episode_id)): Path<(Uuid, Uuid)>,
+    State(state): State<AppContext>,
+    Json(input): Json<MoveParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Editor, &state.pool).await?;
+    let res = playlist::move_episode(id, user.id, episode_id, input.after, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new(
+            "episode not in playlist",
+            StatusCode::NOT_FOUND,
+        ));
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_playlist_members(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Viewer, &state.pool).await?;
+    let members = playlist::get_members(id, &state.pool).await?;
+    Ok(Json(members))
+}
+
+pub async fn share_playlist(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Json(invite): Json<PlaylistInvite>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Owner, &state.pool).await?;
+    if invite.role == PlaylistRole::Owner {
+        return Err(ApiError::new(
+            "role must be viewer or editor",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let member =
+        playlist::share_playlist(id, user.id, invite.user.trim(), invite.role, &state.pool)
+            .await?
+            .ok_or_else(|| ApiError::new("user not found", StatusCode::NOT_FOUND))?;
+    Ok(Json(member))
+}
+
+/// Owners can remove anyone, members only themselves
+pub async fn unshare_playlist(
+    Extension(user): Extension<User>,
+    Path((id, member_id)): Path<(Uuid, Uuid)>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let required = if member_id == user.id {
+        PlaylistRole::Viewer
+    } else {
+        PlaylistRole::Owner
+    };
+    require_role(id, user.id, required, &state.pool).await?;
+    let res = playlist::unshare_playlist(id, user.id, member_id, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new("member not found", StatusCode::NOT_FOUND));
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_playlist_edits(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Query(params): Query<PaginationParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_role(id, user.id, PlaylistRole::Viewer, &state.pool).await?;
+    let (offset, limit) = (params.offset.unwrap_or(0), params.limit.unwrap_or(50));
+    let edits = playlist::get_edits(id, offset, limit, &state.pool).await?;
+    Ok(Json(edits))
+}
@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::user::User,
+    error::ApiError,
+    services::{feed, playlist, queue},
+};
+
+use super::models::MoveParams;
+
+#[derive(Deserialize)]
+pub struct QueuePlaylistParams {
+    /// Put the episodes at the front rather than the end
+    #[serde(default)]
+    next: bool,
+}
+
+pub async fn get_queue(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let episodes = queue::get_queue(user.id, &state.pool).await?;
+    Ok(Json(episodes))
+}
+
+pub async fn append_to_queue(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    add_to_queue(user, id, false, state).await
+}
+
+/// "Play next", also used to bring an already queued episode to the front
+pub async fn play_next(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    add_to_queue(user, id, true, state).await
+}
+
+async fn add_to_queue(
+    user: User,
+    episode_id: Uuid,
+    next: bool,
+    state: AppContext,
+) -> Result<StatusCode, ApiError> {
+    if feed::get_episode(episode_id, user.id, &state.pool)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::new("episode not found", StatusCode::NOT_FOUND));
+    }
+    queue::add_to_queue(user.id, episode_id, next, &state.pool).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Plays a playlist the user can see by queueing its episodes
+pub async fn queue_playlist(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Query(params): Query<QueuePlaylistParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    if playlist::get_role(id, user.id, &state.pool)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::new("playlist not found", StatusCode::NOT_FOUND));
+    }
+    queue::add_playlist_to_queue(user.id, id, params.next, &state.pool).await?;
+    let episodes = queue::get_queue(user.id, &state.pool).await?;
+    Ok(Json(episodes))
+}
+
+pub async fn move_queue_item(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Json(input): Json<MoveParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let res = queue::move_queue_item(user.id, id, input.after, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new("episode not queued", StatusCode::NOT_FOUND));
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn remove_from_queue(
+    Extension(user): Extension<User>,
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let res = queue::remove_from_queue(user.id, id, &state.pool).await?;
+    if !res {
+        return Err(ApiError::new("episode not queued", StatusCode::NOT_FOUND));
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn clear_queue(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    let res = queue::clear_queue(user.id, &state.pool).await?;
+    if !res {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn shuffle_queue(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+) -> Result<impl IntoResponse, ApiError> {
+    queue::shuffle_queue(user.id, &state.pool).await?;
+    let episodes = queue::get_queue(user.id, &state.pool).await?;
+    Ok(Json(episodes))
+}
@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::user::User,
+    error::ApiError,
+    services::search::{self, SearchFilter},
+};
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    q: String,
+    /// Only look through the user's subscriptions
+    #[serde(default)]
+    subscribed: bool,
+    channel_id: Option<Uuid>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+pub async fn search(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    Query(params): Query<SearchParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Err(ApiError::new(
+            "search query is empty",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let filter = SearchFilter {
+        subscribed_only: params.subscribed,
+        channel_id: params.channel_id,
+        from: params.from,
+        to: params.to,
+    };
+    let (offset, limit) = (params.offset.unwrap_or(0), params.limit.unwrap_or(15));
+    let results = search::search(user.id, query, &filter, offset, limit, &state.pool).await?;
+    Ok(Json(results))
+}
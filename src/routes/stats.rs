@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{Datelike, Duration, NaiveDate};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    config::AppContext,
+    core::user::User,
+    error::ApiError,
+    services::{
+        feed::{self, FeedView},
+        stats,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct StatsParams {
+    /// First day of the range, a year before `to` by default
+    from: Option<NaiveDate>,
+    /// Last day of the range, today by default
+    to: Option<NaiveDate>,
+    /// IANA timezone days and hours are counted in, UTC by default
+    tz: Option<String>,
+    /// Year to review, the year of `to` by default
+    year: Option<i32>,
+}
+
+pub async fn get_stats(
+    Extension(user): Extension<User>,
+    State(state): State<AppContext>,
+    Query(params): Query<StatsParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let timezone = params.tz.as_deref().unwrap_or("UTC");
+    if !feed::is_known_timezone(timezone, &state.pool).await? {
+        return Err(ApiError::new("unknown timezone", StatusCode::BAD_REQUEST));
+    }
+    let to = match params.to {
+        Some(to) => to,
+        None => stats::today(timezone, &state.pool).await?,
+    };
+    let from = params.from.unwrap_or(to - Duration::days(364));
+    if from > to {
+        return Err(ApiError::new(
+            "from must not be after to",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let year = params.year.unwrap_or(to.year());
+    let (Some(year_start), Some(year_end)) = (
+        NaiveDate::from_ymd_opt(year, 1, 1),
+        NaiveDate::from_ymd_opt(year, 12, 31),
+    ) else {
+        return Err(ApiError::new("invalid year", StatusCode::BAD_REQUEST));
+    };
+
+    let range = feed::view_range(FeedView::Custom { from, to }, timezone, &state.pool)
+        .await?
+        .ok_or_else(|| ApiError::new("unknown timezone", StatusCode::BAD_REQUEST))?;
+    let year_range = feed::view_range(
+        FeedView::Custom {
+            from: year_start,
+            to: year_end,
+        },
+        timezone,
+        &state.pool,
+    )
+    .await?
+    .ok_or_else(|| ApiError::new("unknown timezone", StatusCode::BAD_REQUEST))?;
+
+    let listening = stats::get_stats(user.id, range, timezone, &state.pool).await?;
+    let year_in_review =
+        stats::get_year_in_review(user.id, year, year_range, timezone, &state.pool).await?;
+    Ok(Json(json!({
+        "timezone": timezone,
+        "from": range.0.timestamp_micros(),
+        "to": range.1.timestamp_micros(),
+        "stats": listening,
+        "year_in_review": year_in_review
+    })))
+}
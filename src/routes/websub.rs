@@ -0,0 +1,108 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    response::IntoResponse,
+};
+use http::{HeaderMap, StatusCode};
+use serde::Deserialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::AppContext,
+    core::websub::{verify_signature, DEFAULT_LEASE_SECONDS},
+    error::ApiError,
+    services::{image, websub},
+};
+
+#[derive(Deserialize)]
+pub struct VerificationParams {
+    #[serde(rename = "hub.mode")]
+    mode: String,
+    #[serde(rename = "hub.topic")]
+    topic: String,
+    #[serde(rename = "hub.challenge")]
+    challenge: Option<String>,
+    #[serde(rename = "hub.lease_seconds")]
+    lease_seconds: Option<i64>,
+}
+
+/// Hubs call this to verify that we actually asked for the (un)subscription
+pub async fn verify_intent(
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    Query(params): Query<VerificationParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let subscription = websub::get_subscription(id, &state.pool)
+        .await?
+        .filter(|s| s.topic == params.topic);
+
+    match params.mode.as_str() {
+        "subscribe" => {
+            let challenge = params
+                .challenge
+                .ok_or(ApiError::new("missing challenge", StatusCode::BAD_REQUEST))?;
+            if subscription.is_none() {
+                return Err(ApiError::new(
+                    "subscription not found",
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+            let lease_seconds = params.lease_seconds.unwrap_or(DEFAULT_LEASE_SECONDS);
+            websub::confirm_subscription(id, lease_seconds, &state.pool).await?;
+            info!("WebSub subscription for channel {id} verified for {lease_seconds}s");
+            Ok((StatusCode::OK, challenge))
+        }
+        "unsubscribe" => {
+            // only agree to drop subscriptions we no longer hold
+            let challenge = params
+                .challenge
+                .ok_or(ApiError::new("missing challenge", StatusCode::BAD_REQUEST))?;
+            if subscription.is_some_and(|s| !s.denied) {
+                return Err(ApiError::new(
+                    "subscription still active",
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+            Ok((StatusCode::OK, challenge))
+        }
+        "denied" => {
+            // hub refused, the channel falls back to polling
+            warn!("WebSub hub denied subscription for channel {id}");
+            websub::deny_subscription(id, &state.pool).await?;
+            Ok((StatusCode::OK, String::new()))
+        }
+        _ => Err(ApiError::new("unknown hub.mode", StatusCode::BAD_REQUEST)),
+    }
+}
+
+/// Content distribution from the hub, carrying the updated feed
+pub async fn receive_content(
+    Path(id): Path<Uuid>,
+    State(state): State<AppContext>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, ApiError> {
+    let subscription = websub::get_subscription(id, &state.pool)
+        .await?
+        .filter(|s| s.verified)
+        .ok_or(ApiError::new("subscription not found", StatusCode::GONE))?;
+
+    let signature = headers
+        .get("X-Hub-Signature")
+        .and_then(|value| value.to_str().ok());
+    let authentic = signature
+        .map(|signature| verify_signature(&subscription.secret, signature, &body))
+        .unwrap_or(false);
+    if !authentic {
+        // the spec requires acknowledging the request while ignoring its content
+        warn!("Ignoring WebSub push with invalid signature for channel {id}");
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    let data = websub::apply_push(id, &body, &state.pool)
+        .await
+        .map_err(|err| ApiError::new(&err.to_string(), StatusCode::BAD_REQUEST))?;
+    image::spawn_artwork_sync(data, state.config, state.fetch_limiter, state.pool);
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::core::bookmark::{share_url, Bookmark};
+use crate::core::rss::PodcastEpisodeDbResult;
+
+const BOOKMARK_QUERY: &str = r#"
+    SELECT b.id, b.episode_id, e.title AS episode_title, e.channel_id, c.title AS channel_title,
+        b.position, b.note, b.created_at, b.updated_at
+    FROM bookmark AS b
+    JOIN episode AS e ON e.id = b.episode_id
+    JOIN channel AS c ON c.id = e.channel_id
+"#;
+
+/// Returns false when the episode was already starred
+pub async fn star_episode(user_id: Uuid, episode_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let result = sqlx::query!(
+        "INSERT INTO episode_star(user_id, episode_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        user_id,
+        episode_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn unstar_episode(user_id: Uuid, episode_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM episode_star WHERE user_id = $1 AND episode_id = $2",
+        user_id,
+        episode_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Most recently starred first
+pub async fn get_starred_episodes(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<PodcastEpisodeDbResult>> {
+    let episodes = sqlx::query_as!(
+        PodcastEpisodeDbResult,
+        r#"
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position as "position?", p.updated_at as "position_updated_at?"
+        FROM episode_star AS s
+        JOIN episode AS e ON e.id = s.episode_id
+        JOIN channel AS c ON c.id = e.channel_id
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = s.user_id
+        WHERE s.user_id = $1
+        ORDER BY s.created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(episodes)
+}
+
+/// The user's bookmarks, newest first, or in playback order when limited to one episode.
+/// A `None` limit returns all of them.
+pub async fn get_bookmarks(
+    user_id: Uuid,
+    episode_id: Option<Uuid>,
+    offset: i64,
+    limit: Option<i64>,
+    public_url: &str,
+    pool: &PgPool,
+) -> Result<Vec<Bookmark>> {
+    let query = format!(
+        r#"
+        {BOOKMARK_QUERY}
+        WHERE b.user_id = $1 AND ($2::uuid IS NULL OR b.episode_id = $2)
+        ORDER BY CASE WHEN $2::uuid IS NULL THEN b.created_at END DESC, b.position, b.created_at
+        OFFSET $3 LIMIT $4
+        "#
+    );
+    let mut bookmarks = sqlx::query_as::<_, Bookmark>(&query)
+        .bind(user_id)
+        .bind(episode_id)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    for bookmark in &mut bookmarks {
+        bookmark.share_url = share_url(public_url, bookmark.id);
+    }
+    Ok(bookmarks)
+}
+
+pub async fn get_bookmark(
+    user_id: Uuid,
+    bookmark_id: Uuid,
+    public_url: &str,
+    pool: &PgPool,
+) -> Result<Option<Bookmark>> {
+    let query = format!("{BOOKMARK_QUERY} WHERE b.user_id = $1 AND b.id = $2");
+    let bookmark = sqlx::query_as::<_, Bookmark>(&query)
+        .bind(user_id)
+        .bind(bookmark_id)
+        .fetch_optional(pool)
+        .await?
+        .map(|bookmark| Bookmark {
+            share_url: share_url(public_url, bookmark.id),
+            ..bookmark
+        });
+    Ok(bookmark)
+}
+
+pub async fn create_bookmark(
+    user_id: Uuid,
+    episode_id: Uuid,
+    position: f64,
+    note: Option<&str>,
+    pool: &PgPool,
+) -> Result<Uuid> {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO bookmark(id, user_id, episode_id, position, note) VALUES ($1, $2, $3, $4, $5)",
+        id,
+        user_id,
+        episode_id,
+        position,
+        note
+    )
+    .execute(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Moves the bookmark and/or replaces its note, an empty note removes it
+pub async fn update_bookmark(
+    user_id: Uuid,
+    bookmark_id: Uuid,
+    position: Option<f64>,
+    note: Option<&str>,
+    pool: &PgPool,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE bookmark
+        SET position = COALESCE($3, position),
+            note = CASE WHEN $4::text IS NULL THEN note ELSE NULLIF($4, '') END,
+            updated_at = now()
+        WHERE user_id = $1 AND id = $2
+        "#,
+        user_id,
+        bookmark_id,
+        position,
+        note
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_bookmark(user_id: Uuid, bookmark_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM bookmark WHERE user_id = $1 AND id = $2",
+        user_id,
+        bookmark_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Enclosure and position a share link points at. Share links are public, so nothing else
+/// about the bookmark, such as its note, is read here.
+pub async fn get_shared_bookmark(
+    bookmark_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<(String, f64)>> {
+    let target = sqlx::query!(
+        r#"
+        SELECT e.audio_link, b.position
+        FROM bookmark AS b
+        JOIN episode AS e ON e.id = b.episode_id
+        WHERE b.id = $1
+        "#,
+        bookmark_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| (row.audio_link, row.position));
+    Ok(target)
+}
@@ -1,9 +1,102 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::core::rss::PodcastChannel;
+use crate::config::Config;
+use crate::core::{
+    directory::DirectoryFeed,
+    limiter::FetchLimiter,
+    opml::OpmlOutline,
+    rss::{get_rss_data, get_uncached_rss_data, FetchResult, PodcastChannel, RssData},
+};
+
+use super::{
+    download::delete_files, feed::delta_update_feed, image::sync_artwork,
+    websub::ensure_subscription,
+};
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ImportStatus {
+    Added { channel_id: Uuid },
+    AlreadySubscribed { channel_id: Uuid },
+    Failed { error: String },
+}
+
+/// A subscribed channel along with the user's settings for it
+#[derive(Serialize, Debug, FromRow)]
+pub struct Subscription {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub channel: PodcastChannel,
+    /// From -2 (lowest) to 2 (highest), 0 by default
+    pub priority: i32,
+    #[sqlx(flatten)]
+    pub settings: SubscriptionSettings,
+}
+
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct SubscriptionSettings {
+    pub playback_speed: f64,
+    /// Seconds skipped at the start of each episode
+    pub skip_intro: i32,
+    /// Seconds skipped at the end, the episode counts as played once they are reached
+    pub skip_outro: i32,
+    /// New episodes go to the end of the queue
+    pub auto_queue: bool,
+    /// Newest episodes to keep downloaded, `null` when off
+    pub download_latest: Option<i32>,
+    /// Download episodes while they are queued
+    pub download_queued: bool,
+    pub notifications: bool,
+}
+
+/// Changes to a subscription's settings, absent fields are left as they are
+#[derive(Deserialize, Debug, Default)]
+pub struct SettingsUpdate {
+    pub playback_speed: Option<f64>,
+    pub skip_intro: Option<i32>,
+    pub skip_outro: Option<i32>,
+    pub auto_queue: Option<bool>,
+    /// `null` turns the rule off
+    #[serde(default, with = "serde_with::rust::double_option")]
+    pub download_latest: Option<Option<i32>>,
+    pub download_queued: Option<bool>,
+    pub notifications: Option<bool>,
+}
+
+impl SettingsUpdate {
+    /// Describes the first out of range value
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self
+            .playback_speed
+            .is_some_and(|speed| !(0.25..=4.0).contains(&speed))
+        {
+            return Err("playback_speed must be between 0.25 and 4");
+        }
+        if self.skip_intro.is_some_and(|s| s < 0) || self.skip_outro.is_some_and(|s| s < 0) {
+            return Err("skip_intro and skip_outro can't be negative");
+        }
+        if self.download_latest.flatten().is_some_and(|keep| keep < 1) {
+            return Err("download_latest must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+// Outcome of importing a single OPML outline
+#[derive(Serialize, Debug)]
+pub struct FeedImportReport {
+    pub xml_url: String,
+    pub title: Option<String>,
+    #[serde(flatten)]
+    pub status: ImportStatus,
+}
 
 pub async fn add_channel(channel: &PodcastChannel, pool: &PgPool) -> Result<bool> {
     let PodcastChannel {
@@ -16,11 +109,14 @@ pub async fn add_channel(channel: &PodcastChannel, pool: &PgPool) -> Result<bool
         tags,
         num_episodes: _,
         image,
+        podcast_guid,
+        locked,
+        image_id: _,
     } = channel;
     let rows_affected = sqlx::query(
         r#"
-        INSERT INTO channel(id, title, rss_link, website_link, author, description, tags, image)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        INSERT INTO channel(id, title, rss_link, website_link, author, description, tags, image, podcast_guid, locked)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
     "#,
     )
     .bind(id)
@@ -31,6 +127,8 @@ pub async fn add_channel(channel: &PodcastChannel, pool: &PgPool) -> Result<bool
     .bind(description)
     .bind(tags)
     .bind(image)
+    .bind(podcast_guid)
+    .bind(locked)
     .execute(pool)
     .await?
     .rows_affected();
@@ -38,11 +136,16 @@ pub async fn add_channel(channel: &PodcastChannel, pool: &PgPool) -> Result<bool
     Ok(rows_affected > 0)
 }
 
-pub async fn get_channels(pool: &PgPool) -> Result<Vec<PodcastChannel>> {
+// Channels with a live WebSub lease get pushed updates instead of being polled
+pub async fn get_polled_channels(pool: &PgPool) -> Result<Vec<PodcastChannel>> {
     let channels = sqlx::query_as!(
         PodcastChannel,
         r#"
-        SELECT *, COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = id), 0) as num_episodes FROM channel
+        SELECT *, COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = channel.id AND episode.removed_at IS NULL), 0) as num_episodes FROM channel
+        WHERE id NOT IN (
+            SELECT channel_id FROM websub_subscription
+            WHERE verified AND lease_expires_at > now()
+        )
         "#,
     )
     .fetch_all(pool)
@@ -50,54 +153,346 @@ pub async fn get_channels(pool: &PgPool) -> Result<Vec<PodcastChannel>> {
     Ok(channels)
 }
 
-pub async fn get_channel_last_published(
-    pool: &PgPool,
-    channel_id: Uuid,
-) -> Result<Option<DateTime<Utc>>> {
-    let last_published = sqlx::query!(
+pub async fn get_channel(id: Uuid, pool: &PgPool) -> Result<Option<PodcastChannel>> {
+    let channel = sqlx::query_as!(
+        PodcastChannel,
         r#"
-        SELECT MAX(published) FROM episode
-        WHERE channel_id = $1
-        GROUP BY channel_id
-    "#,
-        channel_id
+        SELECT *, COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = $1 AND episode.removed_at IS NULL), 0) as num_episodes FROM channel WHERE id = $1
+        "#,
+        id
     )
     .fetch_optional(pool)
     .await?;
-
-    if let Some(record) = last_published {
-        Ok(record.max)
-    } else {
-        Ok(None)
-    }
+    Ok(channel)
 }
 
-pub async fn get_channel(id: Uuid, pool: &PgPool) -> Result<Option<PodcastChannel>> {
+pub async fn get_channel_by_rss_link(
+    rss_link: &str,
+    pool: &PgPool,
+) -> Result<Option<PodcastChannel>> {
     let channel = sqlx::query_as!(
         PodcastChannel,
         r#"
-        SELECT *, COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = $1), 0) as num_episodes FROM channel WHERE id = $1
+        SELECT *, COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = channel.id AND episode.removed_at IS NULL), 0) as num_episodes FROM channel WHERE rss_link = $1
         "#,
-        id
+        rss_link
     )
     .fetch_optional(pool)
     .await?;
     Ok(channel)
 }
 
-pub async fn get_subscriptions(pool: &PgPool, user_id: Uuid) -> Result<Vec<PodcastChannel>> {
-    let channels = sqlx::query_as!(
-       PodcastChannel,
+/// Stores a freshly fetched channel unless we already know it, by link or by id, and binds the data to it
+pub async fn store_channel(data: &mut RssData, pool: &PgPool) -> Result<Uuid> {
+    let channel_id = match get_channel_by_rss_link(&data.channel.rss_link, pool).await? {
+        Some(existing) => existing.id,
+        None => {
+            if get_channel(data.channel.id, pool).await?.is_none() {
+                add_channel(&data.channel, pool).await?;
+            }
+            data.channel.id
+        }
+    };
+    data.bind_to_channel(channel_id);
+    Ok(channel_id)
+}
+
+/// Points a channel at the feed's new location, unless another channel already lives there
+pub async fn update_rss_link(channel_id: Uuid, rss_link: &str, pool: &PgPool) -> Result<bool> {
+    let rows_affected = sqlx::query!(
+        r#"
+        UPDATE channel SET rss_link = $2
+        WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM channel WHERE rss_link = $2 AND id <> $1)
+        "#,
+        channel_id,
+        rss_link
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(rows_affected > 0)
+}
+
+/// Merges a channel into the one already living at the feed's new location and deletes it.
+/// Subscriptions move over, and so does what users did with its episodes: onto the survivor's
+/// copy of an episode where there is one, otherwise the episode itself moves.
+/// Returns the surviving channel, `None` when the feed didn't move or no other channel has
+/// that link.
+pub async fn merge_into_channel_at(
+    channel_id: Uuid,
+    data: &RssData,
+    config: &Config,
+    pool: &PgPool,
+) -> Result<Option<Uuid>> {
+    let Some(rss_link) = data.moved_to.as_deref() else {
+        return Ok(None);
+    };
+    let Some(survivor) = get_channel_by_rss_link(rss_link, pool)
+        .await?
+        .filter(|survivor| survivor.id != channel_id)
+    else {
+        return Ok(None);
+    };
+    // any feed can name any link as its new home, so only follow the host's redirects
+    // or a move between feeds of the same podcast
+    let same_podcast =
+        data.channel.podcast_guid.is_some() && data.channel.podcast_guid == survivor.podcast_guid;
+    if !data.moved_by_redirect && !same_podcast {
+        return Err(anyhow!(
+            "the move was only announced and the feeds don't share a podcast:guid"
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+    // episodes the survivor also has, under another guid, by enclosure or by title and date
+    sqlx::query(
+        r#"
+        CREATE TEMPORARY TABLE merged_episode ON COMMIT DROP AS
+        SELECT DISTINCT ON (d.id) d.id AS old_id, s.id AS new_id
+        FROM episode AS d
+        JOIN episode AS s ON s.channel_id = $2
+            AND (s.audio_link = d.audio_link OR (s.title = d.title AND s.published = d.published))
+        WHERE d.channel_id = $1
+        ORDER BY d.id, s.audio_link = d.audio_link DESC
+        "#,
+    )
+    .bind(channel_id)
+    .bind(survivor.id)
+    .execute(&mut tx)
+    .await?;
+    // where the user already has something on the survivor's episode, it is kept or combined
+    let statements = [
+        r#"
+        INSERT INTO user_watch_history(user_id, episode_id, first_listened_at, last_listened_at,
+            seconds_listened, completion, state, play_count)
+        SELECT wh.user_id, m.new_id, wh.first_listened_at, wh.last_listened_at, wh.seconds_listened,
+            wh.completion, wh.state, wh.play_count
+        FROM user_watch_history AS wh JOIN merged_episode AS m ON m.old_id = wh.episode_id
+        ON CONFLICT (user_id, episode_id) DO UPDATE
+        SET first_listened_at = LEAST(user_watch_history.first_listened_at, EXCLUDED.first_listened_at),
+            last_listened_at = GREATEST(user_watch_history.last_listened_at, EXCLUDED.last_listened_at),
+            seconds_listened = user_watch_history.seconds_listened + EXCLUDED.seconds_listened,
+            completion = GREATEST(user_watch_history.completion, EXCLUDED.completion),
+            state = CASE WHEN 'played' IN (user_watch_history.state, EXCLUDED.state) THEN 'played'
+                ELSE user_watch_history.state END,
+            play_count = user_watch_history.play_count + EXCLUDED.play_count
+        "#,
+        r#"
+        INSERT INTO playback_position(user_id, episode_id, position, duration, device, updated_at)
+        SELECT p.user_id, m.new_id, p.position, p.duration, p.device, p.updated_at
+        FROM playback_position AS p JOIN merged_episode AS m ON m.old_id = p.episode_id
+        ON CONFLICT (user_id, episode_id) DO UPDATE
+        SET position = EXCLUDED.position, duration = EXCLUDED.duration, device = EXCLUDED.device,
+            updated_at = EXCLUDED.updated_at
+        WHERE EXCLUDED.updated_at > playback_position.updated_at
+        "#,
+        r#"
+        INSERT INTO listening_hour(user_id, episode_id, hour, seconds)
+        SELECT lh.user_id, m.new_id, lh.hour, lh.seconds
+        FROM listening_hour AS lh JOIN merged_episode AS m ON m.old_id = lh.episode_id
+        ON CONFLICT (user_id, hour, episode_id) DO UPDATE
+        SET seconds = listening_hour.seconds + EXCLUDED.seconds
+        "#,
+        r#"
+        INSERT INTO queue_item(user_id, episode_id, sort_key, added_at)
+        SELECT q.user_id, m.new_id, q.sort_key, q.added_at
+        FROM queue_item AS q JOIN merged_episode AS m ON m.old_id = q.episode_id
+        ON CONFLICT DO NOTHING
+        "#,
+        r#"
+        INSERT INTO playlist_item(playlist_id, episode_id, sort_key, added_by, added_at)
+        SELECT pi.playlist_id, m.new_id, pi.sort_key, pi.added_by, pi.added_at
+        FROM playlist_item AS pi JOIN merged_episode AS m ON m.old_id = pi.episode_id
+        ON CONFLICT DO NOTHING
+        "#,
+        r#"
+        INSERT INTO episode_star(user_id, episode_id, created_at)
+        SELECT st.user_id, m.new_id, st.created_at
+        FROM episode_star AS st JOIN merged_episode AS m ON m.old_id = st.episode_id
+        ON CONFLICT DO NOTHING
+        "#,
+        "UPDATE bookmark AS b SET episode_id = m.new_id FROM merged_episode AS m WHERE m.old_id = b.episode_id",
+        // the survivor's copy is fetched anew, the old file goes with the download below
+        r#"
+        INSERT INTO download(episode_id)
+        SELECT DISTINCT m.new_id FROM user_download AS ud JOIN merged_episode AS m ON m.old_id = ud.episode_id
+        ON CONFLICT DO NOTHING
+        "#,
+        r#"
+        INSERT INTO user_download(user_id, episode_id, automatic, evicted, created_at, dismissed)
+        SELECT ud.user_id, m.new_id, ud.automatic, ud.evicted, ud.created_at, ud.dismissed
+        FROM user_download AS ud JOIN merged_episode AS m ON m.old_id = ud.episode_id
+        ON CONFLICT DO NOTHING
+        "#,
+    ];
+    for statement in statements {
+        sqlx::query(statement).execute(&mut tx).await?;
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO user_subscriptions(user_id, channel_id, download_latest, download_queued, priority,
+            playback_speed, skip_intro, skip_outro, auto_queue, notifications)
+        SELECT user_id, $2, download_latest, download_queued, priority, playback_speed, skip_intro,
+            skip_outro, auto_queue, notifications
+        FROM user_subscriptions WHERE channel_id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+        channel_id,
+        survivor.id
+    )
+    .execute(&mut tx)
+    .await?;
+    // episodes only the old channel has, e.g. ones older than the new feed goes back
+    sqlx::query(
+        r#"
+        UPDATE episode SET channel_id = $2
+        WHERE channel_id = $1 AND id NOT IN (SELECT old_id FROM merged_episode)
+        "#,
+    )
+    .bind(channel_id)
+    .bind(survivor.id)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query(
+        r#"
+        UPDATE podcast_person SET channel_id = $2
+        WHERE channel_id = $1 AND episode_id IS NOT NULL
+        AND episode_id NOT IN (SELECT old_id FROM merged_episode)
+        "#,
+    )
+    .bind(channel_id)
+    .bind(survivor.id)
+    .execute(&mut tx)
+    .await?;
+    // the survivor's episodes are downloaded anew, so the old files go once this commits
+    let stale_downloads: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        DELETE FROM download WHERE episode_id IN (SELECT old_id FROM merged_episode)
+        RETURNING episode_id
+        "#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    sqlx::query!("DELETE FROM channel WHERE id = $1", channel_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    delete_files(config, stale_downloads.iter()).await;
+    Ok(Some(survivor.id))
+}
+
+pub async fn get_subscriptions(pool: &PgPool, user_id: Uuid) -> Result<Vec<Subscription>> {
+    let subscriptions = sqlx::query_as(
         r#"
-        SELECT channel.*, COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = channel.id), 0) as num_episodes FROM user_subscriptions
+        SELECT channel.*, COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = channel.id AND episode.removed_at IS NULL), 0) as num_episodes,
+            user_subscriptions.priority, user_subscriptions.playback_speed, user_subscriptions.skip_intro,
+            user_subscriptions.skip_outro, user_subscriptions.auto_queue, user_subscriptions.download_latest,
+            user_subscriptions.download_queued, user_subscriptions.notifications
+        FROM user_subscriptions
         LEFT JOIN channel ON channel.id = channel_id
         WHERE user_id = $1
+        ORDER BY user_subscriptions.priority DESC, channel.title
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(subscriptions)
+}
+
+pub async fn update_subscription_settings(
+    user_id: Uuid,
+    channel_id: Uuid,
+    update: &SettingsUpdate,
+    pool: &PgPool,
+) -> Result<bool> {
+    let rows_affected = sqlx::query!(
+        r#"
+        UPDATE user_subscriptions
+        SET playback_speed = COALESCE($3, playback_speed),
+            skip_intro = COALESCE($4, skip_intro),
+            skip_outro = COALESCE($5, skip_outro),
+            auto_queue = COALESCE($6, auto_queue),
+            download_latest = CASE WHEN $7 THEN $8 ELSE download_latest END,
+            download_queued = COALESCE($9, download_queued),
+            notifications = COALESCE($10, notifications)
+        WHERE user_id = $1 AND channel_id = $2
+        "#,
+        user_id,
+        channel_id,
+        update.playback_speed,
+        update.skip_intro,
+        update.skip_outro,
+        update.auto_queue,
+        update.download_latest.is_some(),
+        update.download_latest.flatten(),
+        update.download_queued,
+        update.notifications
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(rows_affected > 0)
+}
+
+/// Settings of the subscription an episode belongs to, `None` when the user isn't subscribed
+pub async fn get_episode_settings(
+    user_id: Uuid,
+    episode_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<SubscriptionSettings>> {
+    let settings = sqlx::query_as!(
+        SubscriptionSettings,
+        r#"
+        SELECT us.playback_speed, us.skip_intro, us.skip_outro, us.auto_queue, us.download_latest,
+            us.download_queued, us.notifications
+        FROM episode AS e
+        JOIN user_subscriptions AS us ON us.channel_id = e.channel_id
+        WHERE us.user_id = $1 AND e.id = $2
         "#,
+        user_id,
+        episode_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(settings)
+}
+
+/// Priority of each subscribed channel, for ordering the user's episodes
+pub async fn get_subscription_priorities(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<HashMap<Uuid, i32>> {
+    let priorities = sqlx::query!(
+        "SELECT channel_id, priority FROM user_subscriptions WHERE user_id = $1",
         user_id
     )
     .fetch_all(pool)
     .await?;
-    Ok(channels)
+    Ok(priorities
+        .into_iter()
+        .map(|row| (row.channel_id, row.priority))
+        .collect())
+}
+
+pub async fn set_subscription_priority(
+    user_id: Uuid,
+    channel_id: Uuid,
+    priority: i32,
+    pool: &PgPool,
+) -> Result<bool> {
+    let rows_affected = sqlx::query!(
+        "UPDATE user_subscriptions SET priority = $3 WHERE user_id = $1 AND channel_id = $2",
+        user_id,
+        channel_id,
+        priority
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(rows_affected > 0)
 }
 
 pub async fn add_subscription(user_id: Uuid, channel_id: Uuid, pool: &PgPool) -> Result<bool> {
@@ -113,6 +508,50 @@ pub async fn add_subscription(user_id: Uuid, channel_id: Uuid, pool: &PgPool) ->
     Ok(rows_affected > 0)
 }
 
+pub async fn is_subscribed(user_id: Uuid, channel_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let subscription = sqlx::query!(
+        "SELECT channel_id FROM user_subscriptions WHERE user_id = $1 AND channel_id = $2",
+        user_id,
+        channel_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(subscription.is_some())
+}
+
+/// Points directory results at the channels we already store and flags the user's subscriptions
+pub async fn match_directory_feeds(
+    user_id: Uuid,
+    feeds: &mut [DirectoryFeed],
+    pool: &PgPool,
+) -> Result<()> {
+    let rss_links: Vec<String> = feeds
+        .iter()
+        .map(|feed| feed.channel.rss_link.clone())
+        .collect();
+    let known = sqlx::query!(
+        r#"
+        SELECT c.id, c.rss_link, us.user_id IS NOT NULL AS "subscribed!"
+        FROM channel AS c
+        LEFT JOIN user_subscriptions AS us ON us.channel_id = c.id AND us.user_id = $1
+        WHERE c.rss_link = ANY($2)
+        "#,
+        user_id,
+        &rss_links
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for feed in feeds.iter_mut() {
+        if let Some(channel) = known.iter().find(|c| c.rss_link == feed.channel.rss_link) {
+            feed.channel.id = channel.id;
+            feed.subscribed = channel.subscribed;
+        }
+    }
+    Ok(())
+}
+
 pub async fn delete_subscription(user_id: Uuid, channel_id: Uuid, pool: &PgPool) -> Result<bool> {
     let rows_affected = sqlx::query!(
         "DELETE FROM user_subscriptions WHERE user_id = $1 AND channel_id = $2",
@@ -125,3 +564,86 @@ pub async fn delete_subscription(user_id: Uuid, channel_id: Uuid, pool: &PgPool)
 
     Ok(rows_affected > 0)
 }
+
+pub async fn import_subscriptions(
+    user_id: Uuid,
+    outlines: Vec<OpmlOutline>,
+    redis_conn: &redis::aio::ConnectionManager,
+    pool: &PgPool,
+    config: &Config,
+    limiter: &FetchLimiter,
+) -> Vec<FeedImportReport> {
+    let mut seen = HashSet::new();
+    let outlines = outlines
+        .into_iter()
+        .filter(|outline| seen.insert(outline.xml_url.clone()));
+
+    // one broken feed shouldn't stop the rest of the import
+    stream::iter(outlines)
+        .map(|outline| {
+            let mut redis_conn = redis_conn.clone();
+            async move {
+                let status = match import_feed(
+                    user_id,
+                    &outline.xml_url,
+                    &mut redis_conn,
+                    pool,
+                    config,
+                    limiter,
+                )
+                .await
+                {
+                    Ok(status) => status,
+                    Err(err) => ImportStatus::Failed {
+                        error: err.to_string(),
+                    },
+                };
+                FeedImportReport {
+                    xml_url: outline.xml_url,
+                    title: outline.title,
+                    status,
+                }
+            }
+        })
+        .buffered(config.fetch_concurrency.max(1))
+        .collect()
+        .await
+}
+
+async fn import_feed(
+    user_id: Uuid,
+    rss_link: &str,
+    redis_conn: &mut redis::aio::ConnectionManager,
+    pool: &PgPool,
+    config: &Config,
+    limiter: &FetchLimiter,
+) -> Result<ImportStatus> {
+    // channels already on the server are kept fresh by the refresh job, skip fetching them again
+    let channel_id = if let Some(channel) = get_channel_by_rss_link(rss_link, pool).await? {
+        channel.id
+    } else {
+        let mut data = {
+            let _permit = limiter.acquire(rss_link).await;
+            match get_rss_data(rss_link, redis_conn).await {
+                // cached, but under a link no channel has, e.g. after the feed moved
+                FetchResult::NotModified => get_uncached_rss_data(rss_link, redis_conn).await,
+                fetched => fetched,
+            }
+        }
+        .into_data()?;
+        let channel_id = store_channel(&mut data, pool).await?;
+        delta_update_feed(pool, &data, config.mark_removed_episodes).await?;
+        ensure_subscription(channel_id, data.websub.as_ref(), config, pool).await;
+        if let Err(err) = sync_artwork(&data, config, limiter, pool).await {
+            warn!("Could not update artwork of {rss_link}: {err}");
+        }
+        channel_id
+    };
+
+    if is_subscribed(user_id, channel_id, pool).await? {
+        return Ok(ImportStatus::AlreadySubscribed { channel_id });
+    }
+    add_subscription(user_id, channel_id, pool).await?;
+
+    Ok(ImportStatus::Added { channel_id })
+}
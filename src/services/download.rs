@@ -0,0 +1,529 @@
+// Episode downloads: subscription rules, the transfer queue and disk quotas
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::core::download::{file_path, hash_file, partial_path, FileTransfer};
+use crate::core::rss::PodcastEpisodeDbResult;
+
+const MAX_ATTEMPTS: i32 = 5;
+// failed downloads wait attempts * this before being retried
+const RETRY_BACKOFF_MINUTES: i32 = 10;
+// a running download is picked up again if it didn't finish by then, e.g. after a crash
+const LEASE_MINUTES: i32 = 180;
+
+/// A download as seen by one of the users that asked for it
+#[derive(Serialize, Debug, FromRow)]
+pub struct UserDownload {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub episode: PodcastEpisodeDbResult,
+    /// pending, downloading, complete, failed or evicted
+    pub status: String,
+    pub size: i64,
+    pub total_size: Option<i64>,
+    /// Added by the subscription's download rule rather than by hand
+    pub automatic: bool,
+    pub error: Option<String>,
+    #[serde(with = "chrono::serde::ts_microseconds_option")]
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DownloadOverview {
+    pub used_bytes: i64,
+    pub quota_bytes: i64,
+    pub downloads: Vec<UserDownload>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct DownloadSummary {
+    pub completed: usize,
+    pub failed: usize,
+    pub evicted: usize,
+}
+
+struct DueDownload {
+    episode_id: Uuid,
+    audio_link: String,
+    audio_size: Option<i64>,
+    validator: Option<String>,
+}
+
+fn quota_bytes(megabytes: u64) -> i64 {
+    (megabytes * 1024 * 1024) as i64
+}
+
+pub async fn get_user_downloads(
+    user_id: Uuid,
+    config: &Config,
+    pool: &PgPool,
+) -> Result<DownloadOverview> {
+    let downloads: Vec<UserDownload> = sqlx::query_as(
+        r#"
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position, p.updated_at as position_updated_at,
+            CASE WHEN ud.evicted THEN 'evicted' ELSE d.status END AS status,
+            CASE WHEN ud.evicted THEN 0 ELSE d.size END AS size,
+            d.total_size, ud.automatic, d.error, d.completed_at
+        FROM user_download AS ud
+        JOIN download AS d ON d.episode_id = ud.episode_id
+        JOIN episode AS e ON e.id = ud.episode_id
+        LEFT JOIN channel AS c ON c.id = e.channel_id
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = ud.user_id
+        WHERE ud.user_id = $1 AND NOT ud.dismissed
+        ORDER BY ud.created_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(DownloadOverview {
+        used_bytes: downloads.iter().map(|download| download.size).sum(),
+        quota_bytes: quota_bytes(config.download_user_quota_mb),
+        downloads,
+    })
+}
+
+/// Queues the episode for download, or brings back one that failed, was evicted or dismissed
+pub async fn request_download(user_id: Uuid, episode_id: Uuid, pool: &PgPool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO download(episode_id) VALUES ($1)
+        ON CONFLICT (episode_id) DO UPDATE
+        SET last_accessed_at = now(),
+            status = CASE WHEN download.status IN ('failed', 'evicted') THEN 'pending' ELSE download.status END,
+            attempts = CASE WHEN download.status IN ('failed', 'evicted') THEN 0 ELSE download.attempts END,
+            retry_at = CASE WHEN download.status IN ('failed', 'evicted') THEN now() ELSE download.retry_at END
+        "#,
+        episode_id
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_download(user_id, episode_id) VALUES ($1, $2)
+        ON CONFLICT (user_id, episode_id) DO UPDATE SET automatic = false, evicted = false, dismissed = false
+        "#,
+        user_id,
+        episode_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The file itself goes once no other user wants it. Downloads added by a rule are only
+/// dismissed, otherwise the rule would bring them back on its next run.
+pub async fn delete_user_download(user_id: Uuid, episode_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let dismissed = sqlx::query!(
+        r#"
+        UPDATE user_download SET dismissed = true
+        WHERE user_id = $1 AND episode_id = $2 AND automatic AND NOT dismissed
+        "#,
+        user_id,
+        episode_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    let deleted = sqlx::query!(
+        "DELETE FROM user_download WHERE user_id = $1 AND episode_id = $2 AND NOT automatic",
+        user_id,
+        episode_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    Ok(dismissed + deleted > 0)
+}
+
+/// Keeps the newest `keep_latest` episodes of a subscription downloaded, `None` turns that off.
+/// With `queued`, episodes of the subscription are also downloaded while they are in the queue.
+pub async fn set_download_rule(
+    user_id: Uuid,
+    channel_id: Uuid,
+    keep_latest: Option<i32>,
+    queued: bool,
+    pool: &PgPool,
+) -> Result<bool> {
+    let rows_affected = sqlx::query!(
+        r#"
+        UPDATE user_subscriptions SET download_latest = $3, download_queued = $4
+        WHERE user_id = $1 AND channel_id = $2
+        "#,
+        user_id,
+        channel_id,
+        keep_latest,
+        queued
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(rows_affected > 0)
+}
+
+/// MIME type of the stored file when the episode is downloaded, counting as an access for eviction
+pub async fn get_completed_download(episode_id: Uuid, pool: &PgPool) -> Result<Option<String>> {
+    let download = sqlx::query!(
+        r#"
+        UPDATE download SET last_accessed_at = now()
+        WHERE episode_id = $1 AND status = 'complete'
+        RETURNING mime_type
+        "#,
+        episode_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(download.map(|download| {
+        download
+            .mime_type
+            .unwrap_or_else(|| "application/octet-stream".to_string())
+    }))
+}
+
+/// Applies subscription rules, downloads everything due and brings usage back under the quotas
+pub async fn run_downloads(config: &Config, pool: &PgPool) -> Result<DownloadSummary> {
+    apply_download_rules(pool).await?;
+    let mut summary = DownloadSummary {
+        evicted: remove_unwanted_files(config, pool).await?,
+        ..Default::default()
+    };
+
+    let concurrency = config.download_concurrency.max(1);
+    loop {
+        let due = claim_due_downloads(concurrency as i64, pool).await?;
+        if due.is_empty() {
+            break;
+        }
+        let results = stream::iter(due)
+            .map(|download| process_download(download, config, pool))
+            .buffer_unordered(concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        for result in results {
+            match result {
+                Ok(()) => summary.completed += 1,
+                Err(_) => summary.failed += 1,
+            }
+        }
+        summary.evicted += enforce_quotas(config, pool).await?;
+    }
+    Ok(summary)
+}
+
+/// Syncs automatic downloads with the newest episodes of every subscription that has a rule
+async fn apply_download_rules(pool: &PgPool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let statements = [
+        r#"
+        CREATE TEMPORARY TABLE wanted_download ON COMMIT DROP AS
+        SELECT us.user_id, e.id AS episode_id FROM user_subscriptions AS us
+        CROSS JOIN LATERAL (
+            SELECT id FROM episode
+            WHERE channel_id = us.channel_id AND removed_at IS NULL
+            ORDER BY published DESC
+            LIMIT us.download_latest
+        ) AS e
+        WHERE us.download_latest > 0
+        UNION
+        SELECT q.user_id, q.episode_id FROM queue_item AS q
+        JOIN episode AS e ON e.id = q.episode_id
+        JOIN user_subscriptions AS us ON us.user_id = q.user_id AND us.channel_id = e.channel_id
+        WHERE us.download_queued AND e.removed_at IS NULL
+        "#,
+        r#"
+        DELETE FROM user_download AS ud WHERE automatic AND NOT EXISTS (
+            SELECT 1 FROM wanted_download AS w WHERE w.user_id = ud.user_id AND w.episode_id = ud.episode_id
+        )
+        "#,
+        "INSERT INTO download(episode_id) SELECT DISTINCT episode_id FROM wanted_download ON CONFLICT DO NOTHING",
+        // existing rows win, so evicted, dismissed and manual downloads stay as they are
+        r#"
+        INSERT INTO user_download(user_id, episode_id, automatic)
+        SELECT user_id, episode_id, true FROM wanted_download
+        ON CONFLICT DO NOTHING
+        "#,
+        // files evicted for everyone before are fetched again for users that still want them
+        r#"
+        UPDATE download AS d SET status = 'pending', attempts = 0, retry_at = now()
+        WHERE status = 'evicted'
+        AND EXISTS (
+            SELECT 1 FROM user_download AS ud
+            WHERE ud.episode_id = d.episode_id AND NOT ud.evicted AND NOT ud.dismissed
+        )
+        "#,
+    ];
+    for statement in statements {
+        sqlx::query(statement).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn claim_due_downloads(limit: i64, pool: &PgPool) -> Result<Vec<DueDownload>> {
+    let due = sqlx::query_as!(
+        DueDownload,
+        r#"
+        WITH due AS (
+            SELECT episode_id FROM download AS d
+            WHERE status IN ('pending', 'downloading', 'failed') AND attempts < $2 AND retry_at <= now()
+            AND EXISTS (
+                SELECT 1 FROM user_download AS ud
+                WHERE ud.episode_id = d.episode_id AND NOT ud.evicted AND NOT ud.dismissed
+            )
+            ORDER BY created_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        UPDATE download AS d
+        SET status = 'downloading', attempts = attempts + 1, retry_at = now() + make_interval(mins => $3)
+        FROM due, episode AS e
+        WHERE d.episode_id = due.episode_id AND e.id = d.episode_id
+        RETURNING d.episode_id, e.audio_link, e.audio_size, d.validator
+        "#,
+        limit,
+        MAX_ATTEMPTS,
+        LEASE_MINUTES
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(due)
+}
+
+async fn process_download(download: DueDownload, config: &Config, pool: &PgPool) -> Result<()> {
+    let storage = &config.download_storage_path;
+    let partial = partial_path(storage, download.episode_id);
+    let result = async {
+        tokio::fs::create_dir_all(storage).await?;
+        let transfer =
+            FileTransfer::start(&download.audio_link, &partial, download.validator.as_deref())
+                .await?;
+        sqlx::query!(
+            "UPDATE download SET validator = $2, total_size = $3, mime_type = $4 WHERE episode_id = $1",
+            download.episode_id,
+            transfer.validator,
+            transfer.total_size.map(|size| size as i64),
+            transfer.mime_type
+        )
+        .execute(pool)
+        .await?;
+        let total_size = transfer.total_size;
+        let size = transfer.write_to(&partial).await?;
+        verify_size(size, total_size, download.audio_size)?;
+
+        let largest = quota_bytes(config.download_quota_mb.min(config.download_user_quota_mb));
+        if size as i64 > largest {
+            tokio::fs::remove_file(&partial).await.ok();
+            return Ok(Err(format!("file is larger than the download quota of {largest} bytes")));
+        }
+
+        let hashed = partial.clone();
+        let sha256 = tokio::task::spawn_blocking(move || hash_file(&hashed)).await??;
+        let path = file_path(storage, download.episode_id);
+        tokio::fs::rename(&partial, &path).await?;
+        let rows_affected = sqlx::query!(
+            r#"
+            UPDATE download
+            SET status = 'complete', size = $2, sha256 = $3, error = NULL, completed_at = now(), last_accessed_at = now()
+            WHERE episode_id = $1 AND status = 'downloading'
+            "#,
+            download.episode_id,
+            size as i64,
+            sha256
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        // everyone lost interest while we were downloading
+        if rows_affected == 0 {
+            tokio::fs::remove_file(&path).await.ok();
+        }
+        anyhow::Ok(Ok(()))
+    }
+    .await;
+
+    let (error, permanent) = match result {
+        Ok(Ok(())) => return Ok(()),
+        Ok(Err(error)) => (error, true),
+        Err(err) => (err.to_string(), false),
+    };
+    warn!("Downloading {} failed: {error}", download.audio_link);
+    let size = tokio::fs::metadata(&partial)
+        .await
+        .map(|metadata| metadata.len() as i64)
+        .unwrap_or(0);
+    sqlx::query!(
+        r#"
+        UPDATE download
+        SET status = 'failed', error = $2, size = $3,
+            attempts = CASE WHEN $4 THEN $5 ELSE attempts END,
+            retry_at = now() + make_interval(mins => attempts * $6)
+        WHERE episode_id = $1
+        "#,
+        download.episode_id,
+        error,
+        size,
+        permanent,
+        MAX_ATTEMPTS,
+        RETRY_BACKOFF_MINUTES
+    )
+    .execute(pool)
+    .await?;
+    Err(anyhow!(error))
+}
+
+/// The host's announced size must match exactly. Without one, the enclosure length is the
+/// best we have, but publishers often get it slightly wrong, so only a shorter file fails.
+fn verify_size(size: u64, total_size: Option<u64>, enclosure_size: Option<i64>) -> Result<()> {
+    match total_size {
+        Some(total) if size != total => Err(anyhow!("download ended at {size} of {total} bytes")),
+        None if enclosure_size.is_some_and(|expected| (size as i64) < expected) => Err(anyhow!(
+            "download is {size} bytes, the feed announced {} bytes",
+            enclosure_size.unwrap_or_default()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Evicts the least recently used files until every user and the server as a whole fit their quota
+async fn enforce_quotas(config: &Config, pool: &PgPool) -> Result<usize> {
+    let user_quota = quota_bytes(config.download_user_quota_mb);
+    let over_quota = sqlx::query!(
+        r#"
+        SELECT ud.user_id, SUM(d.size)::bigint AS "used!" FROM user_download AS ud
+        JOIN download AS d ON d.episode_id = ud.episode_id
+        WHERE NOT ud.evicted AND NOT ud.dismissed
+        GROUP BY ud.user_id
+        HAVING SUM(d.size)::bigint > $1
+        "#,
+        user_quota
+    )
+    .fetch_all(pool)
+    .await?;
+    for user in over_quota {
+        let files = sqlx::query!(
+            r#"
+            SELECT d.episode_id, d.size FROM user_download AS ud
+            JOIN download AS d ON d.episode_id = ud.episode_id
+            WHERE ud.user_id = $1 AND NOT ud.evicted AND NOT ud.dismissed AND d.status = 'complete'
+            ORDER BY d.last_accessed_at
+            "#,
+            user.user_id
+        )
+        .fetch_all(pool)
+        .await?;
+        let evicted = least_recently_used(
+            files.iter().map(|f| (f.episode_id, f.size)),
+            user.used - user_quota,
+        );
+        sqlx::query!(
+            "UPDATE user_download SET evicted = true WHERE user_id = $1 AND episode_id = ANY($2)",
+            user.user_id,
+            &evicted
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    let global_quota = quota_bytes(config.download_quota_mb);
+    let used = sqlx::query_scalar!(
+        r#"SELECT COALESCE(SUM(size), 0)::bigint AS "used!" FROM download WHERE status <> 'evicted'"#
+    )
+    .fetch_one(pool)
+    .await?;
+    if used > global_quota {
+        let files = sqlx::query!(
+            "SELECT episode_id, size FROM download WHERE status = 'complete' ORDER BY last_accessed_at"
+        )
+        .fetch_all(pool)
+        .await?;
+        let evicted = least_recently_used(
+            files.iter().map(|f| (f.episode_id, f.size)),
+            used - global_quota,
+        );
+        sqlx::query!(
+            "UPDATE user_download SET evicted = true WHERE episode_id = ANY($1)",
+            &evicted
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    remove_unwanted_files(config, pool).await
+}
+
+/// Files, oldest first, whose sizes add up to at least `excess` bytes
+fn least_recently_used(files: impl Iterator<Item = (Uuid, i64)>, excess: i64) -> Vec<Uuid> {
+    let mut freed = 0;
+    files
+        .take_while(|(_, size)| {
+            let needed = freed < excess;
+            freed += size;
+            needed
+        })
+        .map(|(episode_id, _)| episode_id)
+        .collect()
+}
+
+/// Deletes files nobody wants anymore, either unrequested or evicted or dismissed for every user
+async fn remove_unwanted_files(config: &Config, pool: &PgPool) -> Result<usize> {
+    let mut tx = pool.begin().await?;
+    let unrequested = sqlx::query_scalar!(
+        r#"
+        DELETE FROM download AS d
+        WHERE NOT EXISTS (SELECT 1 FROM user_download AS ud WHERE ud.episode_id = d.episode_id)
+        RETURNING episode_id
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let evicted = sqlx::query_scalar!(
+        r#"
+        UPDATE download AS d
+        SET status = 'evicted', size = 0, validator = NULL, sha256 = NULL, completed_at = NULL
+        WHERE status <> 'evicted'
+        AND NOT EXISTS (
+            SELECT 1 FROM user_download AS ud
+            WHERE ud.episode_id = d.episode_id AND NOT ud.evicted AND NOT ud.dismissed
+        )
+        RETURNING episode_id
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    delete_files(config, unrequested.iter().chain(&evicted)).await;
+    if !evicted.is_empty() {
+        debug!("Evicted {} downloads", evicted.len());
+    }
+    Ok(evicted.len())
+}
+
+/// Removes the stored and partial files of downloads whose rows are already gone
+pub async fn delete_files(config: &Config, episode_ids: impl Iterator<Item = &Uuid>) {
+    let storage = &config.download_storage_path;
+    for episode_id in episode_ids {
+        for path in [
+            file_path(storage, *episode_id),
+            partial_path(storage, *episode_id),
+        ] {
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Could not delete {}: {err}", path.display());
+                }
+            }
+        }
+    }
+}
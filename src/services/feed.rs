@@ -1,45 +1,90 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
 
-use crate::core::rss::get_rss_data;
 use crate::core::rss::PodcastEpisode;
 use crate::core::rss::PodcastEpisodeDbResult;
 use crate::core::rss::RssData;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::debug;
 use uuid::Uuid;
 
-use super::channel::get_channel_last_published;
-use super::channel::get_channels;
+use crate::config::Config;
+use crate::core::limiter::FetchLimiter;
+
+use super::channel::get_polled_channels;
+use super::podcast::store_podcast_tags;
+use super::queue::queue_new_episodes;
+use super::scheduler::{poll_channels, RefreshSummary};
 
 pub async fn update_all_feeds(
-    redis_conn: &mut redis::aio::ConnectionManager,
+    redis_conn: &redis::aio::ConnectionManager,
     pool: &PgPool,
-) -> anyhow::Result<()> {
-    let channels = get_channels(pool).await?;
-    for source in channels.iter().map(|s| s.rss_link.clone()) {
-        if let Some(rss_data) = get_rss_data(&source, redis_conn).await {
-            delta_update_feed(pool, &rss_data).await?;
-        }
-    }
-    Ok(())
+    config: &Config,
+    limiter: &FetchLimiter,
+) -> anyhow::Result<RefreshSummary> {
+    let channels = get_polled_channels(pool)
+        .await?
+        .into_iter()
+        .map(|channel| (channel.id, channel.rss_link))
+        .collect();
+    Ok(poll_channels(channels, redis_conn, pool, config, limiter).await)
 }
 
-pub async fn delta_update_feed(pool: &PgPool, data: &RssData) -> anyhow::Result<()> {
-    let recent_date = get_channel_last_published(pool, data.channel.id).await?;
-    let update_episodes = if let Some(recent_date) = recent_date {
-        let slice = data.episodes.as_slice();
-        let low = slice.partition_point(|e| e.published <= recent_date);
-        &slice[low..]
+/// Reconciles stored episodes with the feed: new and backdated episodes are inserted,
+/// edited ones updated, and with `mark_removed` ones that vanished from the feed marked as
+/// removed, which only makes sense when `data` is the complete feed.
+/// Podcasting 2.0 tags of the channel and episodes are replaced along the way.
+/// New episodes are queued for subscribers with auto-queue on. Returns how many were added.
+pub async fn delta_update_feed(
+    pool: &PgPool,
+    data: &RssData,
+    mark_removed: bool,
+) -> anyhow::Result<u64> {
+    let mut new_episodes = Vec::new();
+    let mut updated_episodes = 0;
+    let mut tx = pool.begin().await?;
+    for episode in &data.episodes {
+        match upsert_episode(episode, &mut tx).await? {
+            Some(true) => new_episodes.push(episode.id),
+            Some(false) => updated_episodes += 1,
+            None => {}
+        }
+    }
+    let removed_episodes = if mark_removed {
+        mark_removed_episodes(data, &mut tx).await?
     } else {
-        &data.episodes[..]
+        0
     };
+    store_podcast_tags(data, &mut tx).await?;
+    tx.commit().await?;
+    if !new_episodes.is_empty() {
+        queue_new_episodes(data.channel.id, &new_episodes, pool).await?;
+    }
 
-    let tx = pool.begin().await?;
-    for episode in update_episodes {
-        add_episode(episode, pool).await?;
+    if updated_episodes > 0 || removed_episodes > 0 {
+        debug!(
+            "Channel {}: {updated_episodes} episodes updated, {removed_episodes} removed",
+            data.channel.id
+        );
     }
-    tx.commit().await?;
+    Ok(new_episodes.len() as u64)
+}
 
-    Ok(())
+/// How the user's episodes are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedSort {
+    /// Newest first
+    #[default]
+    Date,
+    /// Newest day first, and within a day higher priority subscriptions first
+    Priority,
 }
 
 pub async fn get_subscription_episodes(
@@ -47,44 +92,177 @@ pub async fn get_subscription_episodes(
     pool: &PgPool,
     offset: i64,
     limit: i64,
+    sort: FeedSort,
+    timezone: &str,
 ) -> Result<Vec<PodcastEpisodeDbResult>> {
     let episodes = sqlx::query_as!(
         PodcastEpisodeDbResult,
         r#"
-        SELECT e.*, c.title as channel_title, c.image as channel_image
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position as "position?", p.updated_at as "position_updated_at?"
         FROM user_subscriptions AS us
         LEFT JOIN episode AS e ON e.channel_id = us.channel_id
         LEFT JOIN channel AS c ON c.id = e.channel_id
-        WHERE user_id = $1
-        ORDER BY published DESC
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = us.user_id
+        WHERE us.user_id = $1 AND e.removed_at IS NULL
+        ORDER BY CASE WHEN $4 THEN (e.published AT TIME ZONE $5)::date END DESC,
+            CASE WHEN $4 THEN us.priority END DESC,
+            published DESC
         OFFSET $2
         LIMIT $3
         "#,
         user_id,
         offset,
-        limit
+        limit,
+        sort == FeedSort::Priority,
+        timezone
     )
     .fetch_all(pool)
     .await?;
     Ok(episodes)
 }
 
+/// A date range of the feed, in calendar terms of the user's timezone
+#[derive(Debug, Clone, Copy)]
+pub enum FeedView {
+    Today,
+    Week,
+    Month,
+    /// Both days included
+    Custom {
+        from: NaiveDate,
+        to: NaiveDate,
+    },
+}
+
+/// Whether Postgres, which does all the timezone math, knows the IANA timezone
+pub async fn is_known_timezone(timezone: &str, pool: &PgPool) -> Result<bool> {
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1) AS "known!""#,
+        timezone
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(known)
+}
+
+/// Start and end of the view in UTC, `None` when the timezone is unknown
+pub async fn view_range(
+    view: FeedView,
+    timezone: &str,
+    pool: &PgPool,
+) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+    if !is_known_timezone(timezone, pool).await? {
+        return Ok(None);
+    }
+    let (unit, from, to) = match view {
+        FeedView::Today => ("day", None, None),
+        FeedView::Week => ("week", None, None),
+        FeedView::Month => ("month", None, None),
+        FeedView::Custom { from, to } => ("day", Some(from), Some(to)),
+    };
+    let range = sqlx::query!(
+        r#"
+        WITH bounds AS (
+            SELECT COALESCE($3::date::timestamp, date_trunc($1, now() AT TIME ZONE $2)) AS start,
+                COALESCE(($4::date + 1)::timestamp, date_trunc($1, now() AT TIME ZONE $2) + ('1 ' || $1)::interval) AS "end"
+        )
+        SELECT start AT TIME ZONE $2 AS "start!", "end" AT TIME ZONE $2 AS "end!" FROM bounds
+        "#,
+        unit,
+        timezone,
+        from,
+        to
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(Some((range.start, range.end)))
+}
+
+/// Subscribed episodes published within the range, optionally leaving out played ones
+pub async fn get_view_episodes(
+    user_id: Uuid,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    unplayed: bool,
+    sort: FeedSort,
+    timezone: &str,
+    pool: &PgPool,
+) -> Result<Vec<PodcastEpisodeDbResult>> {
+    let episodes = sqlx::query_as!(
+        PodcastEpisodeDbResult,
+        r#"
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position as "position?", p.updated_at as "position_updated_at?"
+        FROM user_subscriptions AS us
+        JOIN episode AS e ON e.channel_id = us.channel_id
+        LEFT JOIN channel AS c ON c.id = e.channel_id
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = us.user_id
+        WHERE us.user_id = $1 AND e.removed_at IS NULL AND e.published >= $2 AND e.published < $3
+        AND (NOT $4 OR NOT EXISTS (
+            SELECT 1 FROM user_watch_history AS wh
+            WHERE wh.user_id = us.user_id AND wh.episode_id = e.id AND wh.state = 'played'
+        ))
+        ORDER BY CASE WHEN $5 THEN (e.published AT TIME ZONE $6)::date END DESC,
+            CASE WHEN $5 THEN us.priority END DESC,
+            e.published DESC, e.id
+        "#,
+        user_id,
+        start,
+        end,
+        unplayed,
+        sort == FeedSort::Priority,
+        timezone
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(episodes)
+}
+
+/// Shuffles into a play order that's the same every time for the same seed and episodes
+pub fn shuffle_episodes(episodes: &mut [PodcastEpisodeDbResult], seed: u64) {
+    episodes.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+}
+
+/// Like [`shuffle_episodes`], but episodes of higher priority subscriptions tend to come earlier:
+/// each priority step doubles the odds (a weighted random order after Efraimidis and Spirakis)
+pub fn shuffle_episodes_by_priority(
+    episodes: &mut Vec<PodcastEpisodeDbResult>,
+    seed: u64,
+    priorities: &HashMap<Uuid, i32>,
+) {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut keyed: Vec<(f64, PodcastEpisodeDbResult)> = episodes
+        .drain(..)
+        .map(|episode| {
+            let priority = priorities.get(&episode.channel_id).copied().unwrap_or(0);
+            let weight = 2f64.powi(priority);
+            (rng.gen::<f64>().powf(1.0 / weight), episode)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    episodes.extend(keyed.into_iter().map(|(_, episode)| episode));
+}
+
 // TODO: Add pagination
 pub async fn get_channel_episodes(
     channel_id: Uuid,
+    user_id: Uuid,
     pool: &PgPool,
 ) -> Result<Vec<PodcastEpisodeDbResult>> {
     let episodes = sqlx::query_as!(
         PodcastEpisodeDbResult,
         r#"
-        SELECT e.*, c.title as channel_title, c.image as channel_image
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position as "position?", p.updated_at as "position_updated_at?"
         FROM episode AS e
         LEFT JOIN channel AS c ON c.id = e.channel_id
-        WHERE channel_id = $1
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = $2
+        WHERE channel_id = $1 AND e.removed_at IS NULL
         ORDER BY published DESC
         LIMIT 20
         "#,
-        channel_id
+        channel_id,
+        user_id
     )
     .fetch_all(pool)
     .await?;
@@ -93,25 +271,34 @@ pub async fn get_channel_episodes(
 
 pub async fn get_episode(
     episode_id: Uuid,
+    user_id: Uuid,
     pool: &PgPool,
 ) -> Result<Option<PodcastEpisodeDbResult>> {
     let episode = sqlx::query_as!(
         PodcastEpisodeDbResult,
         r#"
-        SELECT e.*, c.title as channel_title, c.image as channel_image
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position as "position?", p.updated_at as "position_updated_at?"
         FROM user_subscriptions AS us
         LEFT JOIN episode AS e ON e.channel_id = us.channel_id
         LEFT JOIN channel AS c ON c.id = e.channel_id
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = $2
         WHERE e.id = $1
         "#,
         episode_id,
+        user_id
     )
     .fetch_optional(pool)
     .await?;
     Ok(episode)
 }
 
-pub async fn add_episode(episode: &PodcastEpisode, pool: &PgPool) -> Result<bool> {
+/// Inserts the episode or updates it in place if any of its fields changed.
+/// Returns `Some(true)` when inserted, `Some(false)` when updated and `None` when untouched.
+async fn upsert_episode(
+    episode: &PodcastEpisode,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Option<bool>> {
     let PodcastEpisode {
         channel_id,
         id,
@@ -122,11 +309,50 @@ pub async fn add_episode(episode: &PodcastEpisode, pool: &PgPool) -> Result<bool
         content,
         tags,
         audio_link,
+        audio_type,
+        audio_size,
+        duration,
+        season,
+        episode_number,
+        episode_type,
+        explicit,
+        image,
+        transcripts: _,
+        chapters: _,
+        persons: _,
     } = episode;
-    let rows_affected = sqlx::query(
+    let inserted = sqlx::query_scalar(
         r#"
-        INSERT INTO episode(id, channel_id, website_link, published, title, audio_link, description, content, tags)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        INSERT INTO episode(id, channel_id, website_link, published, title, audio_link, description, content, tags,
+            audio_type, audio_size, duration, season, episode_number, episode_type, explicit, image)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+        ON CONFLICT (id) DO UPDATE SET
+            website_link = EXCLUDED.website_link,
+            published = EXCLUDED.published,
+            title = EXCLUDED.title,
+            audio_link = EXCLUDED.audio_link,
+            description = EXCLUDED.description,
+            content = EXCLUDED.content,
+            tags = EXCLUDED.tags,
+            audio_type = EXCLUDED.audio_type,
+            audio_size = EXCLUDED.audio_size,
+            duration = EXCLUDED.duration,
+            season = EXCLUDED.season,
+            episode_number = EXCLUDED.episode_number,
+            episode_type = EXCLUDED.episode_type,
+            explicit = EXCLUDED.explicit,
+            image = EXCLUDED.image,
+            removed_at = NULL
+        WHERE episode.channel_id = EXCLUDED.channel_id
+        AND (episode.website_link, episode.published, episode.title, episode.audio_link,
+             episode.description, episode.content, episode.tags, episode.audio_type, episode.audio_size,
+             episode.duration, episode.season, episode.episode_number, episode.episode_type,
+             episode.explicit, episode.image, episode.removed_at)
+        IS DISTINCT FROM (EXCLUDED.website_link, EXCLUDED.published, EXCLUDED.title, EXCLUDED.audio_link,
+             EXCLUDED.description, EXCLUDED.content, EXCLUDED.tags, EXCLUDED.audio_type, EXCLUDED.audio_size,
+             EXCLUDED.duration, EXCLUDED.season, EXCLUDED.episode_number, EXCLUDED.episode_type,
+             EXCLUDED.explicit, EXCLUDED.image, NULL::timestamptz)
+        RETURNING (xmax = 0) AS inserted
     "#,
     )
     .bind(id)
@@ -138,8 +364,37 @@ pub async fn add_episode(episode: &PodcastEpisode, pool: &PgPool) -> Result<bool
     .bind(description)
     .bind(content)
     .bind(tags)
-    .execute(pool)
+    .bind(audio_type)
+    .bind(audio_size)
+    .bind(duration)
+    .bind(season)
+    .bind(episode_number)
+    .bind(episode_type)
+    .bind(explicit)
+    .bind(image)
+    .fetch_optional(&mut *tx)
+    .await?;
+    Ok(inserted)
+}
+
+/// Feeds commonly only carry their latest episodes, so only stored episodes within the
+/// date range the feed still covers are considered removed when missing from it
+async fn mark_removed_episodes(data: &RssData, tx: &mut Transaction<'_, Postgres>) -> Result<u64> {
+    let Some(oldest) = data.episodes.first() else {
+        return Ok(0);
+    };
+    let ids: Vec<Uuid> = data.episodes.iter().map(|episode| episode.id).collect();
+    let rows_affected = sqlx::query!(
+        r#"
+        UPDATE episode SET removed_at = now()
+        WHERE channel_id = $1 AND removed_at IS NULL AND published >= $2 AND NOT (id = ANY($3))
+        "#,
+        data.channel.id,
+        oldest.published,
+        &ids
+    )
+    .execute(&mut *tx)
     .await?
     .rows_affected();
-    Ok(rows_affected > 0)
+    Ok(rows_affected)
 }
@@ -1,31 +1,101 @@
-use anyhow::Result;
-use sqlx::PgPool;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::core::rss::PodcastEpisodeDbResult;
 
-pub async fn get_history(user_id: Uuid, pool: &PgPool) -> Result<Vec<PodcastEpisodeDbResult>> {
-    let episodes = sqlx::query_as!(
-        PodcastEpisodeDbResult,
+/// In-progress episodes left alone this long count as abandoned
+const ABANDON_AFTER_DAYS: i32 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryState {
+    Played,
+    InProgress,
+    Abandoned,
+}
+
+impl HistoryState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryState::Played => "played",
+            HistoryState::InProgress => "in_progress",
+            HistoryState::Abandoned => "abandoned",
+        }
+    }
+}
+
+impl TryFrom<String> for HistoryState {
+    type Error = anyhow::Error;
+
+    fn try_from(state: String) -> Result<Self> {
+        match state.as_str() {
+            "played" => Ok(HistoryState::Played),
+            "in_progress" => Ok(HistoryState::InProgress),
+            "abandoned" => Ok(HistoryState::Abandoned),
+            _ => Err(anyhow!("unknown history state {state}")),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub episode: PodcastEpisodeDbResult,
+    #[sqlx(try_from = "String")]
+    pub state: HistoryState,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub first_listened_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub last_listened_at: DateTime<Utc>,
+    pub seconds_listened: f64,
+    /// Furthest point reached, in percent
+    pub completion: f64,
+    /// Times the episode was finished
+    pub play_count: i32,
+}
+
+/// Most recently listened first, optionally only entries in one state or of one channel
+pub async fn get_history(
+    user_id: Uuid,
+    state: Option<HistoryState>,
+    channel_id: Option<Uuid>,
+    offset: i64,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<HistoryEntry>> {
+    let entries = sqlx::query_as(
         r#"
-        SELECT e.*, c.title as channel_title, c.image as channel_image
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position, p.updated_at as position_updated_at,
+            wh.state, wh.first_listened_at, wh.last_listened_at, wh.seconds_listened, wh.completion,
+            wh.play_count
         FROM user_watch_history as wh
-        LEFT JOIN episode AS e ON e.id = wh.episode_id
+        JOIN episode AS e ON e.id = wh.episode_id
         LEFT JOIN channel AS c ON c.id = e.channel_id
-        WHERE user_id = $1
-        ORDER BY published DESC
-        LIMIT 20
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = wh.user_id
+        WHERE wh.user_id = $1
+        AND ($2::text IS NULL OR wh.state = $2)
+        AND ($3::uuid IS NULL OR e.channel_id = $3)
+        ORDER BY wh.last_listened_at DESC, e.id
+        OFFSET $4 LIMIT $5
         "#,
-        user_id
     )
+    .bind(user_id)
+    .bind(state.map(HistoryState::as_str))
+    .bind(channel_id)
+    .bind(offset)
+    .bind(limit)
     .fetch_all(pool)
     .await?;
-    Ok(episodes)
+    Ok(entries)
 }
 
 pub async fn clear_history(user_id: Uuid, pool: &PgPool) -> Result<bool> {
-    let result = sqlx::query_as!(
-        PodcastEpisode,
+    let result = sqlx::query!(
         r#"
         DELETE FROM user_watch_history
         WHERE user_id = $1
@@ -37,17 +107,92 @@ pub async fn clear_history(user_id: Uuid, pool: &PgPool) -> Result<bool> {
     Ok(result.rows_affected() > 0)
 }
 
+/// Records the episode as played, which also takes it off the queue.
+/// Marking it again counts another play. Returns false when the episode doesn't exist.
 pub async fn mark_played(user_id: Uuid, episode_id: Uuid, pool: &PgPool) -> Result<bool> {
-    let result = sqlx::query_as!(
-        PodcastEpisode,
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
         r#"
-        INSERT INTO user_watch_history
-        VALUES($1, $2)
+        INSERT INTO user_watch_history(user_id, episode_id, state, completion, play_count)
+        SELECT $1, id, 'played', 100, 1 FROM episode WHERE id = $2
+        ON CONFLICT (user_id, episode_id) DO UPDATE
+        SET state = 'played',
+            completion = 100,
+            play_count = user_watch_history.play_count + 1,
+            last_listened_at = now()
         "#,
         user_id,
         episode_id
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await?;
+    sqlx::query!(
+        "DELETE FROM queue_item WHERE user_id = $1 AND episode_id = $2",
+        user_id,
+        episode_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
     Ok(result.rows_affected() > 0)
 }
+
+/// Adds a partial play to the history, right after its position was stored, and to the hourly
+/// totals stats are built from. Played episodes stay played, abandoned ones are in progress again.
+pub async fn record_progress(
+    user_id: Uuid,
+    episode_id: Uuid,
+    seconds_listened: f64,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_watch_history(user_id, episode_id, seconds_listened, completion)
+        SELECT p.user_id, p.episode_id, $3,
+            COALESCE(LEAST(100, 100 * p.position / NULLIF(COALESCE(p.duration, e.duration), 0)), 0)
+        FROM playback_position AS p
+        JOIN episode AS e ON e.id = p.episode_id
+        WHERE p.user_id = $1 AND p.episode_id = $2
+        ON CONFLICT (user_id, episode_id) DO UPDATE
+        SET seconds_listened = user_watch_history.seconds_listened + EXCLUDED.seconds_listened,
+            completion = GREATEST(user_watch_history.completion, EXCLUDED.completion),
+            state = CASE WHEN user_watch_history.state = 'played' THEN 'played' ELSE 'in_progress' END,
+            last_listened_at = now()
+        "#,
+        user_id,
+        episode_id,
+        seconds_listened
+    )
+    .execute(&mut *tx)
+    .await?;
+    if seconds_listened > 0.0 {
+        sqlx::query!(
+            r#"
+            INSERT INTO listening_hour(user_id, episode_id, hour, seconds)
+            VALUES ($1, $2, date_trunc('hour', now(), 'UTC'), $3)
+            ON CONFLICT (user_id, hour, episode_id) DO UPDATE
+            SET seconds = listening_hour.seconds + EXCLUDED.seconds
+            "#,
+            user_id,
+            episode_id,
+            seconds_listened
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Marks in-progress episodes nobody got back to as abandoned, returns how many
+pub async fn abandon_stale_entries(pool: &PgPool) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_watch_history SET state = 'abandoned'
+        WHERE state = 'in_progress' AND last_listened_at < now() - make_interval(days => $1)
+        "#,
+        ABANDON_AFTER_DAYS
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
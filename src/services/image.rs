@@ -0,0 +1,188 @@
+// Keeps local copies of channel and episode artwork in step with the feeds
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::{stream, StreamExt};
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::core::{
+    image::{download_image, image_directory, store_variants},
+    limiter::FetchLimiter,
+    rss::RssData,
+};
+
+// artwork rarely changes, so known images are downloaded again at most once a day
+const RECHECK_AFTER_HOURS: i32 = 24;
+// failed images are retried after an hour, doubling with every failure up to a week
+const MAX_RETRY_AFTER_HOURS: i32 = 7 * 24;
+const DOWNLOAD_CONCURRENCY: usize = 4;
+
+pub struct StoredImage {
+    pub id: Uuid,
+    pub content_hash: String,
+    pub mime_type: String,
+}
+
+/// Downloads the artwork a feed references unless it was checked recently,
+/// then points the channel and its episodes at the local copies.
+/// Images that can't be fetched or decoded are skipped, the feed is stored either way,
+/// and they aren't tried again until their backoff has passed.
+pub async fn sync_artwork(
+    data: &RssData,
+    config: &Config,
+    limiter: &FetchLimiter,
+    pool: &PgPool,
+) -> Result<()> {
+    let urls: Vec<String> = data
+        .episodes
+        .iter()
+        .map(|episode| &episode.image)
+        .chain([&data.channel.image])
+        .flatten()
+        .filter(|url| !url.trim().is_empty())
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let checked = sqlx::query_scalar!(
+        r#"
+        SELECT source_url as "source_url!" FROM image
+        WHERE source_url = ANY($1) AND fetched_at > now() - make_interval(hours => $2)
+        UNION ALL
+        SELECT source_url FROM image_failure
+        WHERE source_url = ANY($1)
+        AND fetched_at > now() - make_interval(hours => LEAST(power(2, failure_count - 1), $3)::int)
+        "#,
+        &urls,
+        RECHECK_AFTER_HOURS,
+        MAX_RETRY_AFTER_HOURS as f64
+    )
+    .fetch_all(pool)
+    .await?;
+
+    stream::iter(urls.iter().filter(|url| !checked.contains(url)))
+        .for_each_concurrent(DOWNLOAD_CONCURRENCY, |url| async move {
+            let _permit = limiter.acquire(url).await;
+            let stored = store_image(url, config, pool).await;
+            if let Err(err) = &stored {
+                warn!("Could not store artwork {url}: {err}");
+            }
+            if let Err(err) = record_attempt(url, stored.err(), pool).await {
+                warn!("Could not record download of artwork {url}: {err}");
+            }
+        })
+        .await;
+
+    let channel_id = data.channel.id;
+    sqlx::query!(
+        r#"
+        UPDATE channel SET image_id = i.id
+        FROM channel AS c LEFT JOIN image AS i ON i.source_url = c.image
+        WHERE channel.id = $1 AND c.id = channel.id AND channel.image_id IS DISTINCT FROM i.id
+        "#,
+        channel_id
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE episode SET image_id = i.id
+        FROM episode AS e LEFT JOIN image AS i ON i.source_url = e.image
+        WHERE episode.channel_id = $1 AND e.id = episode.id AND episode.image_id IS DISTINCT FROM i.id
+        "#,
+        channel_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Runs `sync_artwork` in the background, for requests that shouldn't wait on the downloads
+pub fn spawn_artwork_sync(data: RssData, config: Config, limiter: Arc<FetchLimiter>, pool: PgPool) {
+    tokio::spawn(async move {
+        if let Err(err) = sync_artwork(&data, &config, &limiter, &pool).await {
+            warn!(
+                "Could not update artwork of {}: {err}",
+                data.channel.rss_link
+            );
+        }
+    });
+}
+
+/// Downloads an image, regenerating its variants only when the file changed
+async fn store_image(url: &str, config: &Config, pool: &PgPool) -> Result<()> {
+    let id = Uuid::new_v5(&Uuid::NAMESPACE_URL, url.as_bytes());
+    let downloaded = download_image(url).await?;
+
+    let known_hash = sqlx::query_scalar!("SELECT content_hash FROM image WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await?;
+    if known_hash.as_ref() == Some(&downloaded.content_hash) {
+        sqlx::query!("UPDATE image SET fetched_at = now() WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+        return Ok(());
+    }
+
+    let directory = image_directory(&config.image_storage_path, id);
+    let bytes = downloaded.bytes;
+    let info = tokio::task::spawn_blocking(move || store_variants(&bytes, &directory)).await??;
+    sqlx::query!(
+        r#"
+        INSERT INTO image(id, source_url, content_hash, mime_type, width, height)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (id) DO UPDATE
+        SET content_hash = $3, mime_type = $4, width = $5, height = $6, fetched_at = now(), updated_at = now()
+        "#,
+        id,
+        url,
+        downloaded.content_hash,
+        info.mime_type,
+        info.width as i32,
+        info.height as i32
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// remembers failures for the backoff, a successful download clears them
+async fn record_attempt(url: &str, error: Option<anyhow::Error>, pool: &PgPool) -> Result<()> {
+    match error {
+        Some(error) => {
+            sqlx::query!(
+                r#"
+                INSERT INTO image_failure(source_url, error) VALUES ($1, $2)
+                ON CONFLICT (source_url) DO UPDATE
+                SET error = $2, failure_count = image_failure.failure_count + 1, fetched_at = now()
+                "#,
+                url,
+                format!("{error:#}")
+            )
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query!("DELETE FROM image_failure WHERE source_url = $1", url)
+                .execute(pool)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_image(id: Uuid, pool: &PgPool) -> Result<Option<StoredImage>> {
+    let image = sqlx::query_as!(
+        StoredImage,
+        "SELECT id, content_hash, mime_type FROM image WHERE id = $1",
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(image)
+}
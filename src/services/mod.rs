@@ -1,4 +1,16 @@
 pub(crate) mod auth;
+pub(crate) mod bookmark;
 pub(crate) mod channel;
+pub(crate) mod download;
 pub(crate) mod feed;
 pub(crate) mod history;
+pub(crate) mod image;
+pub(crate) mod ordering;
+pub(crate) mod playback;
+pub(crate) mod playlist;
+pub(crate) mod podcast;
+pub(crate) mod queue;
+pub(crate) mod scheduler;
+pub(crate) mod search;
+pub(crate) mod stats;
+pub(crate) mod websub;
@@ -0,0 +1,184 @@
+// Episodes kept in the order users arrange them, with fractional sort keys so a move touches one row.
+// Shared by the queue and playlists, which only differ in the table and what a list belongs to.
+
+use anyhow::{anyhow, Result};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+/// The rows of `table` whose `scope_column` is `scope`, ordered by `sort_key` then `added_at`
+pub struct OrderedList {
+    table: &'static str,
+    scope_column: &'static str,
+    scope: Uuid,
+}
+
+impl OrderedList {
+    pub fn queue(user_id: Uuid) -> Self {
+        Self {
+            table: "queue_item",
+            scope_column: "user_id",
+            scope: user_id,
+        }
+    }
+
+    pub fn playlist(playlist_id: Uuid) -> Self {
+        Self {
+            table: "playlist_item",
+            scope_column: "playlist_id",
+            scope: playlist_id,
+        }
+    }
+
+    /// Moves an episode right behind `after`, or to the front without one.
+    /// Anchoring on an episode rather than an index keeps the move correct when another device
+    /// changed the list in the meantime. Returns false when either episode isn't in the list.
+    /// Callers lock the list first, so concurrent moves can't compute the same sort key.
+    pub async fn move_after(
+        &self,
+        episode_id: Uuid,
+        after: Option<Uuid>,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool> {
+        let present: Vec<Uuid> = sqlx::query_scalar(&format!(
+            "SELECT episode_id FROM {} WHERE {} = $1 AND episode_id = ANY($2)",
+            self.table, self.scope_column
+        ))
+        .bind(self.scope)
+        .bind(
+            [Some(episode_id), after]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        if !present.contains(&episode_id) || after.is_some_and(|after| !present.contains(&after)) {
+            return Ok(false);
+        }
+        if after == Some(episode_id) {
+            return Ok(true);
+        }
+
+        let mut sort_key = self.sort_key_after(episode_id, after, tx).await?;
+        if sort_key.is_none() {
+            // the keys around the spot ran out of precision, space them out again
+            self.renumber(tx).await?;
+            sort_key = self.sort_key_after(episode_id, after, tx).await?;
+        }
+        let sort_key =
+            sort_key.ok_or_else(|| anyhow!("no room in {} after renumbering", self.table))?;
+        sqlx::query(&format!(
+            "UPDATE {} SET sort_key = $3 WHERE {} = $1 AND episode_id = $2",
+            self.table, self.scope_column
+        ))
+        .bind(self.scope)
+        .bind(episode_id)
+        .bind(sort_key)
+        .execute(&mut *tx)
+        .await?;
+        Ok(true)
+    }
+
+    /// A key between `after` and the item following it, see [`key_between`]
+    async fn sort_key_after(
+        &self,
+        episode_id: Uuid,
+        after: Option<Uuid>,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Option<f64>> {
+        let (lower, upper): (Option<f64>, Option<f64>) = sqlx::query_as(&format!(
+            r#"
+            WITH anchor AS (SELECT sort_key FROM {table} WHERE {scope} = $1 AND episode_id = $3)
+            SELECT
+                (SELECT sort_key FROM anchor) AS lower,
+                (SELECT MIN(sort_key) FROM {table}
+                 WHERE {scope} = $1 AND episode_id <> $2
+                 AND ($3::uuid IS NULL OR sort_key > (SELECT sort_key FROM anchor))) AS upper
+            "#,
+            table = self.table,
+            scope = self.scope_column
+        ))
+        .bind(self.scope)
+        .bind(episode_id)
+        .bind(after)
+        .fetch_one(&mut *tx)
+        .await?;
+        Ok(key_between(lower, upper))
+    }
+
+    async fn renumber(&self, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+        sqlx::query(&format!(
+            r#"
+            UPDATE {table} AS item SET sort_key = ordered.n
+            FROM (
+                SELECT episode_id, row_number() OVER (ORDER BY sort_key, added_at)::float8 AS n
+                FROM {table} WHERE {scope} = $1
+            ) AS ordered
+            WHERE item.{scope} = $1 AND item.episode_id = ordered.episode_id
+            "#,
+            table = self.table,
+            scope = self.scope_column
+        ))
+        .bind(self.scope)
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+}
+
+/// A sort key ordering between two neighbours, either of which may be missing at the ends.
+/// `None` when the two are too close for a float to fit in between.
+fn key_between(lower: Option<f64>, upper: Option<f64>) -> Option<f64> {
+    let sort_key = match (lower, upper) {
+        (Some(lower), Some(upper)) => lower + (upper - lower) / 2.0,
+        (Some(lower), None) => lower + 1.0,
+        (None, Some(upper)) => upper - 1.0,
+        (None, None) => 1.0,
+    };
+    let fits =
+        lower.is_none_or(|lower| sort_key > lower) && upper.is_none_or(|upper| sort_key < upper);
+    fits.then_some(sort_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::key_between;
+
+    #[test]
+    fn keys_go_between_neighbours() {
+        assert_eq!(key_between(None, None), Some(1.0));
+        assert_eq!(key_between(Some(3.0), None), Some(4.0));
+        assert_eq!(key_between(None, Some(3.0)), Some(2.0));
+        assert_eq!(key_between(Some(1.0), Some(2.0)), Some(1.5));
+    }
+
+    #[test]
+    fn adjacent_floats_leave_no_room() {
+        let lower = 1.0_f64;
+        let upper = f64::from_bits(lower.to_bits() + 1);
+        assert_eq!(key_between(Some(lower), Some(upper)), None);
+        assert_eq!(key_between(Some(lower), Some(lower)), None);
+    }
+
+    #[test]
+    fn repeated_inserts_run_out_of_precision() {
+        // always inserting right after the first item halves the gap every time
+        let (lower, mut upper) = (1.0, 2.0);
+        let mut inserts = 0;
+        while let Some(key) = key_between(Some(lower), Some(upper)) {
+            assert!(lower < key && key < upper);
+            upper = key;
+            inserts += 1;
+        }
+        // a double has 52 bits of mantissa to split between 1 and 2
+        assert_eq!(inserts, 52);
+    }
+
+    #[test]
+    fn ends_run_out_of_precision_only_at_huge_keys() {
+        let huge = 2.0_f64.powi(53);
+        assert_eq!(key_between(Some(huge), None), None);
+        assert_eq!(key_between(None, Some(-huge)), None);
+        assert_eq!(key_between(Some(huge - 2.0), None), Some(huge - 1.0));
+    }
+}
@@ -0,0 +1,167 @@
+// Where users left off in each episode, reported by players over the WebSocket or REST
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::history::record_progress;
+
+/// Fastest playback speed players offer, see the subscription settings
+const MAX_PLAYBACK_SPEED: f64 = 4.0;
+/// Allowance for reports that arrive late or in a burst
+const REPORT_SLACK_SECONDS: f64 = 5.0;
+
+#[derive(Serialize, Debug)]
+pub struct PlaybackPosition {
+    pub episode_id: Uuid,
+    /// Seconds into the episode
+    pub position: f64,
+    /// Length of the audio in seconds, as reported by the player
+    pub duration: Option<f64>,
+    pub device: Option<String>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Positions and durations are seconds, anything negative or not a number can't be stored
+pub fn is_valid_report(position: f64, duration: Option<f64>) -> bool {
+    let valid = |seconds: f64| seconds.is_finite() && seconds >= 0.0;
+    valid(position) && duration.is_none_or(valid)
+}
+
+/// Records the position and the listening it stands for in the history,
+/// returning false when the episode doesn't exist
+pub async fn save_position(
+    user_id: Uuid,
+    episode_id: Uuid,
+    position: f64,
+    duration: Option<f64>,
+    device: Option<&str>,
+    pool: &PgPool,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let previous = sqlx::query!(
+        r#"
+        SELECT position, EXTRACT(EPOCH FROM now() - updated_at)::float8 AS "elapsed!"
+        FROM playback_position WHERE user_id = $1 AND episode_id = $2
+        FOR UPDATE
+        "#,
+        user_id,
+        episode_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let rows_affected = sqlx::query!(
+        r#"
+        INSERT INTO playback_position(user_id, episode_id, position, duration, device)
+        SELECT $1, id, $3, $4, $5 FROM episode WHERE id = $2
+        ON CONFLICT (user_id, episode_id) DO UPDATE
+        SET position = EXCLUDED.position,
+            duration = COALESCE(EXCLUDED.duration, playback_position.duration),
+            device = EXCLUDED.device,
+            updated_at = now()
+        "#,
+        user_id,
+        episode_id,
+        position,
+        duration,
+        device
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    if rows_affected == 0 {
+        return Ok(false);
+    }
+    let listened = previous.map_or(0.0, |previous| {
+        listened_between(previous.position, position, previous.elapsed)
+    });
+    record_progress(user_id, episode_id, listened, &mut tx).await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Seconds listened between two reported positions. Moving further than the player could
+/// have played in the time between the reports is a seek, and so is moving backwards.
+fn listened_between(previous: f64, current: f64, elapsed: f64) -> f64 {
+    let progress = current - previous;
+    if progress > 0.0 && progress <= elapsed * MAX_PLAYBACK_SPEED + REPORT_SLACK_SECONDS {
+        progress
+    } else {
+        0.0
+    }
+}
+
+pub async fn get_position(
+    user_id: Uuid,
+    episode_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<PlaybackPosition>> {
+    let position = sqlx::query_as!(
+        PlaybackPosition,
+        r#"
+        SELECT episode_id, position, duration, device, updated_at FROM playback_position
+        WHERE user_id = $1 AND episode_id = $2
+        "#,
+        user_id,
+        episode_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(position)
+}
+
+/// The position reported most recently, from any device, i.e. what the player should resume
+pub async fn get_last_position(user_id: Uuid, pool: &PgPool) -> Result<Option<PlaybackPosition>> {
+    let position = sqlx::query_as!(
+        PlaybackPosition,
+        r#"
+        SELECT episode_id, position, duration, device, updated_at FROM playback_position
+        WHERE user_id = $1
+        ORDER BY updated_at DESC
+        LIMIT 1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_report, listened_between};
+
+    #[test]
+    fn counts_regular_playback() {
+        assert_eq!(listened_between(100.0, 130.0, 30.0), 30.0);
+        // sped up playback covers more of the episode than the time that passed
+        assert_eq!(listened_between(100.0, 160.0, 30.0), 60.0);
+        // reports arriving a little late still count
+        assert_eq!(listened_between(0.0, 9.0, 1.0), 9.0);
+    }
+
+    #[test]
+    fn ignores_forward_seeks() {
+        assert_eq!(listened_between(100.0, 1000.0, 30.0), 0.0);
+        assert_eq!(listened_between(0.0, 126.0, 30.0), 0.0);
+    }
+
+    #[test]
+    fn ignores_rewinds_and_pauses() {
+        assert_eq!(listened_between(500.0, 200.0, 30.0), 0.0);
+        assert_eq!(listened_between(500.0, 500.0, 30.0), 0.0);
+    }
+
+    #[test]
+    fn rejects_negative_and_non_finite_reports() {
+        assert!(is_valid_report(0.0, None));
+        assert!(is_valid_report(12.5, Some(3600.0)));
+        assert!(!is_valid_report(-1.0, None));
+        assert!(!is_valid_report(f64::NAN, Some(3600.0)));
+        assert!(!is_valid_report(12.5, Some(-3600.0)));
+        assert!(!is_valid_report(12.5, Some(f64::INFINITY)));
+    }
+}
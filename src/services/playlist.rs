@@ -0,0 +1,497 @@
+// Playlists: ordered episodes owned by one user, optionally shared with others as viewers or editors
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::core::rss::PodcastEpisodeDbResult;
+
+use super::ordering::OrderedList;
+
+/// What a user may do with a playlist, each role includes the ones before it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistRole {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl PlaylistRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaylistRole::Viewer => "viewer",
+            PlaylistRole::Editor => "editor",
+            PlaylistRole::Owner => "owner",
+        }
+    }
+}
+
+impl TryFrom<String> for PlaylistRole {
+    type Error = anyhow::Error;
+
+    fn try_from(role: String) -> Result<Self> {
+        match role.as_str() {
+            "viewer" => Ok(PlaylistRole::Viewer),
+            "editor" => Ok(PlaylistRole::Editor),
+            "owner" => Ok(PlaylistRole::Owner),
+            _ => Err(anyhow!("unknown playlist role {role}")),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct Playlist {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub owner_name: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// The requesting user's role
+    #[sqlx(try_from = "String")]
+    pub role: PlaylistRole,
+    pub episode_count: i64,
+    /// Artwork of the first episode that has any
+    pub image_id: Option<Uuid>,
+    pub image: Option<String>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct PlaylistEpisode {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub episode: PodcastEpisodeDbResult,
+    pub added_by: Option<Uuid>,
+    pub added_by_name: Option<String>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub added_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct PlaylistMember {
+    pub user_id: Uuid,
+    pub name: String,
+    #[sqlx(try_from = "String")]
+    pub role: PlaylistRole,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// An entry of the edit log, names are gone once the account or episode was deleted
+#[derive(Serialize, Debug, FromRow)]
+pub struct PlaylistEdit {
+    pub user_id: Option<Uuid>,
+    pub user_name: Option<String>,
+    /// create, update, add, remove, move, share or unshare
+    pub action: String,
+    pub episode_id: Option<Uuid>,
+    pub episode_title: Option<String>,
+    pub member_id: Option<Uuid>,
+    pub member_name: Option<String>,
+    #[serde(with = "chrono::serde::ts_microseconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+const PLAYLIST_QUERY: &str = r#"
+    SELECT pl.id, pl.owner_id, a.name AS owner_name, pl.name, pl.description,
+        CASE WHEN pl.owner_id = $1 THEN 'owner' ELSE m.role END AS role,
+        (SELECT COUNT(*) FROM playlist_item WHERE playlist_id = pl.id) AS episode_count,
+        art.image_id, art.image, pl.created_at, pl.updated_at
+    FROM playlist AS pl
+    JOIN account AS a ON a.id = pl.owner_id
+    LEFT JOIN playlist_member AS m ON m.playlist_id = pl.id AND m.user_id = $1
+    LEFT JOIN LATERAL (
+        SELECT COALESCE(e.image_id, c.image_id) AS image_id, COALESCE(e.image, c.image) AS image
+        FROM playlist_item AS pi
+        JOIN episode AS e ON e.id = pi.episode_id
+        LEFT JOIN channel AS c ON c.id = e.channel_id
+        WHERE pi.playlist_id = pl.id AND COALESCE(e.image, c.image) IS NOT NULL
+        ORDER BY pi.sort_key, pi.added_at
+        LIMIT 1
+    ) AS art ON true
+    WHERE (pl.owner_id = $1 OR m.user_id IS NOT NULL) AND ($2::uuid IS NULL OR pl.id = $2)
+    ORDER BY pl.updated_at DESC
+"#;
+
+/// Playlists the user owns or was invited to
+pub async fn get_playlists(user_id: Uuid, pool: &PgPool) -> Result<Vec<Playlist>> {
+    let playlists = sqlx::query_as(PLAYLIST_QUERY)
+        .bind(user_id)
+        .bind(None::<Uuid>)
+        .fetch_all(pool)
+        .await?;
+    Ok(playlists)
+}
+
+/// The playlist, if the user may see it
+pub async fn get_playlist(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Playlist>> {
+    let playlist = sqlx::query_as(PLAYLIST_QUERY)
+        .bind(user_id)
+        .bind(playlist_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(playlist)
+}
+
+/// The user's role, `None` when the playlist doesn't exist or isn't shared with them
+pub async fn get_role(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<PlaylistRole>> {
+    let role = sqlx::query_scalar!(
+        r#"
+        SELECT CASE WHEN pl.owner_id = $2 THEN 'owner' ELSE m.role END AS role
+        FROM playlist AS pl
+        LEFT JOIN playlist_member AS m ON m.playlist_id = pl.id AND m.user_id = $2
+        WHERE pl.id = $1
+        "#,
+        playlist_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+    role.map(PlaylistRole::try_from).transpose()
+}
+
+pub async fn get_playlist_episodes(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<PlaylistEpisode>> {
+    let episodes = sqlx::query_as(
+        r#"
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position, p.updated_at as position_updated_at,
+            pi.added_by, a.name AS added_by_name, pi.added_at
+        FROM playlist_item AS pi
+        JOIN episode AS e ON e.id = pi.episode_id
+        LEFT JOIN channel AS c ON c.id = e.channel_id
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = $2
+        LEFT JOIN account AS a ON a.id = pi.added_by
+        WHERE pi.playlist_id = $1
+        ORDER BY pi.sort_key, pi.added_at
+        "#,
+    )
+    .bind(playlist_id)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(episodes)
+}
+
+pub async fn create_playlist(
+    user_id: Uuid,
+    name: &str,
+    description: Option<&str>,
+    pool: &PgPool,
+) -> Result<Uuid> {
+    let id = Uuid::new_v4();
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO playlist(id, owner_id, name, description) VALUES ($1, $2, $3, $4)",
+        id,
+        user_id,
+        name,
+        description
+    )
+    .execute(&mut tx)
+    .await?;
+    log_edit(id, user_id, "create", None, None, &mut tx).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Renames the playlist and/or replaces its description, an empty description removes it
+pub async fn update_playlist(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    name: Option<&str>,
+    description: Option<&str>,
+    pool: &PgPool,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE playlist
+        SET name = COALESCE($2, name),
+            description = CASE WHEN $3::text IS NULL THEN description ELSE NULLIF($3, '') END
+        WHERE id = $1
+        "#,
+        playlist_id,
+        name,
+        description
+    )
+    .execute(&mut tx)
+    .await?;
+    log_edit(playlist_id, user_id, "update", None, None, &mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn delete_playlist(playlist_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let rows_affected = sqlx::query!("DELETE FROM playlist WHERE id = $1", playlist_id)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(rows_affected > 0)
+}
+
+/// Appends the episode, returning false when it's already in the playlist
+pub async fn add_episode(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    episode_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    lock_playlist(playlist_id, &mut tx).await?;
+    let rows_affected = sqlx::query!(
+        r#"
+        INSERT INTO playlist_item(playlist_id, episode_id, sort_key, added_by)
+        SELECT $1, $2, COALESCE(MAX(sort_key), 0) + 1, $3 FROM playlist_item WHERE playlist_id = $1
+        ON CONFLICT (playlist_id, episode_id) DO NOTHING
+        "#,
+        playlist_id,
+        episode_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    if rows_affected == 0 {
+        return Ok(false);
+    }
+    log_edit(playlist_id, user_id, "add", Some(episode_id), None, &mut tx).await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+pub async fn remove_episode(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    episode_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let rows_affected = sqlx::query!(
+        "DELETE FROM playlist_item WHERE playlist_id = $1 AND episode_id = $2",
+        playlist_id,
+        episode_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    if rows_affected == 0 {
+        return Ok(false);
+    }
+    log_edit(
+        playlist_id,
+        user_id,
+        "remove",
+        Some(episode_id),
+        None,
+        &mut tx,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Moves an episode right behind `after`, or to the start without one, like queue moves.
+/// Returns false when either episode isn't in the playlist.
+pub async fn move_episode(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    episode_id: Uuid,
+    after: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    lock_playlist(playlist_id, &mut tx).await?;
+    if !OrderedList::playlist(playlist_id)
+        .move_after(episode_id, after, &mut tx)
+        .await?
+    {
+        return Ok(false);
+    }
+    log_edit(
+        playlist_id,
+        user_id,
+        "move",
+        Some(episode_id),
+        None,
+        &mut tx,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+pub async fn get_members(playlist_id: Uuid, pool: &PgPool) -> Result<Vec<PlaylistMember>> {
+    let members = sqlx::query_as(
+        r#"
+        SELECT m.user_id, a.name, m.role, m.created_at
+        FROM playlist_member AS m
+        JOIN account AS a ON a.id = m.user_id
+        WHERE m.playlist_id = $1
+        ORDER BY m.created_at
+        "#,
+    )
+    .bind(playlist_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(members)
+}
+
+/// Invites the account with this name or email, or changes its role if already invited.
+/// `None` when there's no such account or it's the owner's.
+pub async fn share_playlist(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    name_or_email: &str,
+    role: PlaylistRole,
+    pool: &PgPool,
+) -> Result<Option<PlaylistMember>> {
+    let mut tx = pool.begin().await?;
+    let member = sqlx::query_as(
+        r#"
+        INSERT INTO playlist_member(playlist_id, user_id, role)
+        SELECT pl.id, a.id, $3 FROM playlist AS pl, account AS a
+        WHERE pl.id = $1 AND (a.name = $2 OR a.email = $2) AND a.id <> pl.owner_id
+        ON CONFLICT (playlist_id, user_id) DO UPDATE SET role = EXCLUDED.role
+        RETURNING user_id, (SELECT name FROM account WHERE id = user_id), role, created_at
+        "#,
+    )
+    .bind(playlist_id)
+    .bind(name_or_email)
+    .bind(role.as_str())
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(member): Option<PlaylistMember> = member else {
+        return Ok(None);
+    };
+    log_edit(
+        playlist_id,
+        user_id,
+        "share",
+        None,
+        Some(member.user_id),
+        &mut tx,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Some(member))
+}
+
+pub async fn unshare_playlist(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    member_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let rows_affected = sqlx::query!(
+        "DELETE FROM playlist_member WHERE playlist_id = $1 AND user_id = $2",
+        playlist_id,
+        member_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    if rows_affected == 0 {
+        return Ok(false);
+    }
+    log_edit(
+        playlist_id,
+        user_id,
+        "unshare",
+        None,
+        Some(member_id),
+        &mut tx,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+pub async fn get_edits(
+    playlist_id: Uuid,
+    offset: i64,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<PlaylistEdit>> {
+    let edits = sqlx::query_as(
+        r#"
+        SELECT ed.user_id, a.name AS user_name, ed.action, ed.episode_id, e.title AS episode_title,
+            ed.member_id, m.name AS member_name, ed.created_at
+        FROM playlist_edit AS ed
+        LEFT JOIN account AS a ON a.id = ed.user_id
+        LEFT JOIN episode AS e ON e.id = ed.episode_id
+        LEFT JOIN account AS m ON m.id = ed.member_id
+        WHERE ed.playlist_id = $1
+        ORDER BY ed.created_at DESC, ed.id DESC
+        OFFSET $2
+        LIMIT $3
+        "#,
+    )
+    .bind(playlist_id)
+    .bind(offset)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(edits)
+}
+
+/// Records who changed the playlist, bumping its updated_at
+async fn log_edit(
+    playlist_id: Uuid,
+    user_id: Uuid,
+    action: &str,
+    episode_id: Option<Uuid>,
+    member_id: Option<Uuid>,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO playlist_edit(playlist_id, user_id, action, episode_id, member_id)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        playlist_id,
+        user_id,
+        action,
+        episode_id,
+        member_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE playlist SET updated_at = now() WHERE id = $1",
+        playlist_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Serializes edits of the items, so concurrent editors can't compute the same sort key
+async fn lock_playlist(playlist_id: Uuid, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    sqlx::query!(
+        "SELECT id FROM playlist WHERE id = $1 FOR UPDATE",
+        playlist_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,259 @@
+// Podcasting 2.0 namespace tags stored alongside channels and episodes
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::core::rss::{Chapters, Funding, Person, PodcastEpisodeDbResult, RssData, Transcript};
+
+use super::feed::get_episode;
+
+/// An episode along with its transcripts, chapters, credits and the channel's funding links
+#[derive(Serialize, Debug)]
+pub struct EpisodeDetails {
+    #[serde(flatten)]
+    pub episode: PodcastEpisodeDbResult,
+    pub transcripts: Vec<Transcript>,
+    pub chapters: Option<Chapters>,
+    pub persons: Vec<Person>,
+    pub funding: Vec<Funding>,
+}
+
+/// Replaces the stored tags of the channel and of every episode carried by the feed
+pub async fn store_podcast_tags(data: &RssData, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    let channel_id = data.channel.id;
+    sqlx::query!(
+        "UPDATE channel SET podcast_guid = $2, locked = $3 WHERE id = $1",
+        channel_id,
+        data.channel.podcast_guid,
+        data.channel.locked
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM channel_funding WHERE channel_id = $1",
+        channel_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let (urls, messages): (Vec<_>, Vec<_>) = data
+        .funding
+        .iter()
+        .map(|funding| (funding.url.clone(), funding.message.clone()))
+        .unzip();
+    sqlx::query(
+        r#"
+        INSERT INTO channel_funding(channel_id, url, message)
+        SELECT $1, * FROM UNNEST($2::text[], $3::text[])
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(channel_id)
+    .bind(urls)
+    .bind(messages)
+    .execute(&mut *tx)
+    .await?;
+
+    // episode tags are only replaced for episodes in this document, pushed feeds may be partial
+    let episode_ids: Vec<Uuid> = data.episodes.iter().map(|episode| episode.id).collect();
+    sqlx::query!(
+        "DELETE FROM episode_transcript WHERE episode_id IN (SELECT id FROM episode WHERE channel_id = $1 AND id = ANY($2))",
+        channel_id,
+        &episode_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM episode_chapters WHERE episode_id IN (SELECT id FROM episode WHERE channel_id = $1 AND id = ANY($2))",
+        channel_id,
+        &episode_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM podcast_person WHERE channel_id = $1 AND (episode_id IS NULL OR episode_id = ANY($2))",
+        channel_id,
+        &episode_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let transcripts = data.episodes.iter().flat_map(|episode| {
+        episode
+            .transcripts
+            .iter()
+            .map(move |transcript| (episode.id, transcript))
+    });
+    let (mut ids, mut urls, mut mime_types, mut languages, mut rels) =
+        (vec![], vec![], vec![], vec![], vec![]);
+    for (episode_id, transcript) in transcripts {
+        ids.push(episode_id);
+        urls.push(transcript.url.clone());
+        mime_types.push(transcript.mime_type.clone());
+        languages.push(transcript.language.clone());
+        rels.push(transcript.rel.clone());
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO episode_transcript(episode_id, url, mime_type, language, rel)
+        SELECT t.* FROM UNNEST($2::uuid[], $3::text[], $4::text[], $5::text[], $6::text[])
+            AS t(episode_id, url, mime_type, language, rel)
+        JOIN episode AS e ON e.id = t.episode_id AND e.channel_id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(channel_id)
+    .bind(ids)
+    .bind(urls)
+    .bind(mime_types)
+    .bind(languages)
+    .bind(rels)
+    .execute(&mut *tx)
+    .await?;
+
+    let (mut ids, mut urls, mut mime_types) = (vec![], vec![], vec![]);
+    for episode in &data.episodes {
+        if let Some(chapters) = &episode.chapters {
+            ids.push(episode.id);
+            urls.push(chapters.url.clone());
+            mime_types.push(chapters.mime_type.clone());
+        }
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO episode_chapters(episode_id, url, mime_type)
+        SELECT c.* FROM UNNEST($2::uuid[], $3::text[], $4::text[]) AS c(episode_id, url, mime_type)
+        JOIN episode AS e ON e.id = c.episode_id AND e.channel_id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(channel_id)
+    .bind(ids)
+    .bind(urls)
+    .bind(mime_types)
+    .execute(&mut *tx)
+    .await?;
+
+    let persons =
+        data.persons
+            .iter()
+            .map(|person| (None, person))
+            .chain(data.episodes.iter().flat_map(|episode| {
+                episode
+                    .persons
+                    .iter()
+                    .map(move |person| (Some(episode.id), person))
+            }));
+    let (mut ids, mut names, mut roles, mut groups, mut images, mut hrefs) =
+        (vec![], vec![], vec![], vec![], vec![], vec![]);
+    for (episode_id, person) in persons {
+        ids.push(episode_id);
+        names.push(person.name.clone());
+        roles.push(person.role.clone());
+        groups.push(person.group.clone());
+        images.push(person.image.clone());
+        hrefs.push(person.href.clone());
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO podcast_person(channel_id, episode_id, name, role, person_group, image, href)
+        SELECT $1, p.* FROM UNNEST($2::uuid[], $3::text[], $4::text[], $5::text[], $6::text[], $7::text[])
+            AS p(episode_id, name, role, person_group, image, href)
+        WHERE p.episode_id IS NULL OR EXISTS (SELECT 1 FROM episode WHERE id = p.episode_id AND channel_id = $1)
+        "#,
+    )
+    .bind(channel_id)
+    .bind(ids)
+    .bind(names)
+    .bind(roles)
+    .bind(groups)
+    .bind(images)
+    .bind(hrefs)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_episode_details(
+    episode_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<EpisodeDetails>> {
+    let Some(episode) = get_episode(episode_id, user_id, pool).await? else {
+        return Ok(None);
+    };
+    let transcripts = get_transcripts(episode_id, pool).await?;
+    let chapters = get_chapters(episode_id, pool).await?;
+    let persons = get_persons(episode.channel_id, episode_id, pool).await?;
+    let funding = get_funding(episode.channel_id, pool).await?;
+    Ok(Some(EpisodeDetails {
+        episode,
+        transcripts,
+        chapters,
+        persons,
+        funding,
+    }))
+}
+
+pub async fn get_transcripts(episode_id: Uuid, pool: &PgPool) -> Result<Vec<Transcript>> {
+    let transcripts = sqlx::query_as!(
+        Transcript,
+        "SELECT url, mime_type, language, rel FROM episode_transcript WHERE episode_id = $1",
+        episode_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(transcripts)
+}
+
+pub async fn get_chapters(episode_id: Uuid, pool: &PgPool) -> Result<Option<Chapters>> {
+    let chapters = sqlx::query_as!(
+        Chapters,
+        "SELECT url, mime_type FROM episode_chapters WHERE episode_id = $1",
+        episode_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(chapters)
+}
+
+/// Credits of the episode, falling back to the channel's when the episode lists none
+pub async fn get_persons(channel_id: Uuid, episode_id: Uuid, pool: &PgPool) -> Result<Vec<Person>> {
+    let persons = sqlx::query!(
+        r#"
+        SELECT episode_id, name, role, person_group, image, href FROM podcast_person
+        WHERE channel_id = $1 AND (episode_id = $2 OR episode_id IS NULL)
+        "#,
+        channel_id,
+        episode_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let has_episode_credits = persons.iter().any(|person| person.episode_id.is_some());
+    Ok(persons
+        .into_iter()
+        .filter(|person| person.episode_id.is_some() == has_episode_credits)
+        .map(|person| Person {
+            name: person.name,
+            role: person.role,
+            group: person.person_group,
+            image: person.image,
+            href: person.href,
+        })
+        .collect())
+}
+
+pub async fn get_funding(channel_id: Uuid, pool: &PgPool) -> Result<Vec<Funding>> {
+    let funding = sqlx::query_as!(
+        Funding,
+        "SELECT url, message FROM channel_funding WHERE channel_id = $1",
+        channel_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(funding)
+}
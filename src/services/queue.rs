@@ -0,0 +1,215 @@
+// Up Next: the episodes a user wants to hear, in order, shared by all their devices
+
+use anyhow::Result;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::core::rss::PodcastEpisodeDbResult;
+
+use super::ordering::OrderedList;
+
+pub async fn get_queue(user_id: Uuid, pool: &PgPool) -> Result<Vec<PodcastEpisodeDbResult>> {
+    let episodes = sqlx::query_as!(
+        PodcastEpisodeDbResult,
+        r#"
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position as "position?", p.updated_at as "position_updated_at?"
+        FROM queue_item AS q
+        JOIN episode AS e ON e.id = q.episode_id
+        LEFT JOIN channel AS c ON c.id = e.channel_id
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = q.user_id
+        WHERE q.user_id = $1
+        ORDER BY q.sort_key, q.added_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(episodes)
+}
+
+/// Adds the episode to the end of the queue, or with `next` to the front, moving it there if already queued
+pub async fn add_to_queue(
+    user_id: Uuid,
+    episode_id: Uuid,
+    next: bool,
+    pool: &PgPool,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    lock_queue(user_id, &mut tx).await?;
+    if next {
+        sqlx::query!(
+            r#"
+            INSERT INTO queue_item(user_id, episode_id, sort_key)
+            SELECT $1, $2, COALESCE(MIN(sort_key), 0) - 1 FROM queue_item WHERE user_id = $1 AND episode_id <> $2
+            ON CONFLICT (user_id, episode_id) DO UPDATE SET sort_key = EXCLUDED.sort_key
+            "#,
+            user_id,
+            episode_id
+        )
+        .execute(&mut tx)
+        .await?;
+    } else {
+        sqlx::query!(
+            r#"
+            INSERT INTO queue_item(user_id, episode_id, sort_key)
+            SELECT $1, $2, COALESCE(MAX(sort_key), 0) + 1 FROM queue_item WHERE user_id = $1
+            ON CONFLICT (user_id, episode_id) DO NOTHING
+            "#,
+            user_id,
+            episode_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Queues the playlist's episodes in its order, at the end or with `next` at the front.
+/// With `next`, episodes already queued are moved up as well.
+pub async fn add_playlist_to_queue(
+    user_id: Uuid,
+    playlist_id: Uuid,
+    next: bool,
+    pool: &PgPool,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    lock_queue(user_id, &mut tx).await?;
+    if next {
+        sqlx::query!(
+            r#"
+            INSERT INTO queue_item(user_id, episode_id, sort_key)
+            SELECT $1, pi.episode_id,
+                (SELECT COALESCE(MIN(sort_key), 0) FROM queue_item WHERE user_id = $1)
+                - row_number() OVER (ORDER BY pi.sort_key DESC, pi.added_at DESC)
+            FROM playlist_item AS pi WHERE pi.playlist_id = $2
+            ON CONFLICT (user_id, episode_id) DO UPDATE SET sort_key = EXCLUDED.sort_key
+            "#,
+            user_id,
+            playlist_id
+        )
+        .execute(&mut tx)
+        .await?;
+    } else {
+        sqlx::query!(
+            r#"
+            INSERT INTO queue_item(user_id, episode_id, sort_key)
+            SELECT $1, pi.episode_id,
+                (SELECT COALESCE(MAX(sort_key), 0) FROM queue_item WHERE user_id = $1)
+                + row_number() OVER (ORDER BY pi.sort_key, pi.added_at)
+            FROM playlist_item AS pi WHERE pi.playlist_id = $2
+            ON CONFLICT (user_id, episode_id) DO NOTHING
+            "#,
+            user_id,
+            playlist_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Appends a channel's new episodes, oldest first, to the queue of every subscriber with
+/// auto-queue on
+pub async fn queue_new_episodes(
+    channel_id: Uuid,
+    episode_ids: &[Uuid],
+    pool: &PgPool,
+) -> Result<()> {
+    let user_ids = sqlx::query_scalar!(
+        "SELECT user_id FROM user_subscriptions WHERE channel_id = $1 AND auto_queue",
+        channel_id
+    )
+    .fetch_all(pool)
+    .await?;
+    for user_id in user_ids {
+        let mut tx = pool.begin().await?;
+        lock_queue(user_id, &mut tx).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO queue_item(user_id, episode_id, sort_key)
+            SELECT $1, e.id,
+                (SELECT COALESCE(MAX(sort_key), 0) FROM queue_item WHERE user_id = $1)
+                + row_number() OVER (ORDER BY e.published, e.id)
+            FROM episode AS e WHERE e.id = ANY($2)
+            ON CONFLICT (user_id, episode_id) DO NOTHING
+            "#,
+            user_id,
+            episode_ids
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+/// Moves a queued episode right behind `after`, or to the front without one.
+/// Returns false when either episode isn't queued.
+pub async fn move_queue_item(
+    user_id: Uuid,
+    episode_id: Uuid,
+    after: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    lock_queue(user_id, &mut tx).await?;
+    let moved = OrderedList::queue(user_id)
+        .move_after(episode_id, after, &mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(moved)
+}
+
+pub async fn remove_from_queue(user_id: Uuid, episode_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let rows_affected = sqlx::query!(
+        "DELETE FROM queue_item WHERE user_id = $1 AND episode_id = $2",
+        user_id,
+        episode_id
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(rows_affected > 0)
+}
+
+pub async fn clear_queue(user_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let rows_affected = sqlx::query!("DELETE FROM queue_item WHERE user_id = $1", user_id)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(rows_affected > 0)
+}
+
+pub async fn shuffle_queue(user_id: Uuid, pool: &PgPool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    lock_queue(user_id, &mut tx).await?;
+    sqlx::query!(
+        r#"
+        UPDATE queue_item AS q SET sort_key = shuffled.n
+        FROM (
+            SELECT episode_id, row_number() OVER (ORDER BY random())::float8 AS n
+            FROM queue_item WHERE user_id = $1
+        ) AS shuffled
+        WHERE q.user_id = $1 AND q.episode_id = shuffled.episode_id
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Serializes edits of one user's queue, so concurrent ones can't compute the same sort key
+async fn lock_queue(user_id: Uuid, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock(hashtextextended('queue:' || $1::text, 0))",
+        user_id.to_string()
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
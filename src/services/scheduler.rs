@@ -0,0 +1,315 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    core::{
+        cache::get_cache_ttl,
+        limiter::FetchLimiter,
+        rss::{get_rss_data, FetchResult, FetchStatus},
+        scheduler::{backoff_interval, poll_interval, PollContext, CADENCE_SAMPLE_SIZE},
+    },
+};
+
+use super::{
+    channel::{merge_into_channel_at, update_rss_link},
+    feed::delta_update_feed,
+    image::sync_artwork,
+    websub::ensure_subscription,
+};
+
+#[derive(Serialize, Debug)]
+pub struct ChannelPollSummary {
+    pub channel_id: Uuid,
+    pub rss_link: String,
+    pub status: FetchStatus,
+    pub new_episodes: u64,
+    pub error: Option<String>,
+}
+
+// Outcome of a refresh run, totals plus the per channel breakdown
+#[derive(Serialize, Debug, Default)]
+pub struct RefreshSummary {
+    pub fetched: usize,
+    pub not_modified: usize,
+    pub failed: usize,
+    pub new_episodes: u64,
+    pub channels: Vec<ChannelPollSummary>,
+}
+
+impl RefreshSummary {
+    fn from_results(channels: Vec<ChannelPollSummary>) -> Self {
+        let count = |matches: fn(&FetchStatus) -> bool| {
+            channels.iter().filter(|c| matches(&c.status)).count()
+        };
+        Self {
+            fetched: count(|s| *s == FetchStatus::Updated),
+            not_modified: count(|s| *s == FetchStatus::NotModified),
+            failed: count(FetchStatus::is_failure),
+            new_episodes: channels.iter().map(|c| c.new_episodes).sum(),
+            channels,
+        }
+    }
+}
+
+// Why a channel may have stopped updating, as recorded by its last poll
+#[derive(Serialize, Debug)]
+pub struct ChannelHealth {
+    pub last_status: Option<String>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: i32,
+    pub last_http_status: Option<i32>,
+    pub last_polled_at: DateTime<Utc>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub next_poll_at: DateTime<Utc>,
+}
+
+pub struct DueChannel {
+    pub id: Uuid,
+    pub rss_link: String,
+}
+
+/// Channels whose next poll is due, including ones that were never polled
+pub async fn get_due_channels(pool: &PgPool) -> Result<Vec<DueChannel>> {
+    let channels = sqlx::query_as!(
+        DueChannel,
+        r#"
+        SELECT c.id, c.rss_link FROM channel AS c
+        LEFT JOIN channel_poll AS p ON p.channel_id = c.id
+        WHERE (p.next_poll_at IS NULL OR p.next_poll_at <= now())
+        AND c.id NOT IN (
+            SELECT channel_id FROM websub_subscription
+            WHERE verified AND lease_expires_at > now()
+        )
+        ORDER BY p.next_poll_at ASC NULLS FIRST
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(channels)
+}
+
+pub async fn poll_due_channels(
+    redis_conn: &redis::aio::ConnectionManager,
+    pool: &PgPool,
+    config: &Config,
+    limiter: &FetchLimiter,
+) -> Result<RefreshSummary> {
+    let channels = get_due_channels(pool)
+        .await?
+        .into_iter()
+        .map(|channel| (channel.id, channel.rss_link))
+        .collect();
+    Ok(poll_channels(channels, redis_conn, pool, config, limiter).await)
+}
+
+/// Polls channels concurrently, bounded by the configured global and per-host limits
+pub async fn poll_channels(
+    channels: Vec<(Uuid, String)>,
+    redis_conn: &redis::aio::ConnectionManager,
+    pool: &PgPool,
+    config: &Config,
+    limiter: &FetchLimiter,
+) -> RefreshSummary {
+    let results = stream::iter(channels)
+        .map(|(channel_id, rss_link)| {
+            let mut redis_conn = redis_conn.clone();
+            async move {
+                poll_channel(channel_id, rss_link, &mut redis_conn, pool, config, limiter).await
+            }
+        })
+        .buffer_unordered(config.fetch_concurrency.max(1))
+        .collect::<Vec<ChannelPollSummary>>()
+        .await;
+    RefreshSummary::from_results(results)
+}
+
+/// Fetches a single channel, imports new episodes and schedules its next poll
+pub async fn poll_channel(
+    channel_id: Uuid,
+    rss_link: String,
+    redis_conn: &mut redis::aio::ConnectionManager,
+    pool: &PgPool,
+    config: &Config,
+    limiter: &FetchLimiter,
+) -> ChannelPollSummary {
+    let fetched = {
+        let _permit = limiter.acquire(&rss_link).await;
+        get_rss_data(&rss_link, redis_conn).await
+    };
+
+    let mut outcome = PollOutcome {
+        status: fetched.status(),
+        error: fetched.error_message(),
+        http_status: fetched.http_status().map(|status| status.as_u16() as i32),
+        refresh_hint: None,
+    };
+    let mut new_episodes = 0;
+    if let FetchResult::Updated(mut data) = fetched {
+        data.bind_to_channel(channel_id);
+        if let Some(moved_to) = &data.moved_to {
+            match update_rss_link(channel_id, moved_to, pool).await {
+                Ok(true) => info!("Feed {rss_link} moved to {moved_to}"),
+                // the new location was subscribed to separately, fold this channel into it
+                Ok(false) => {
+                    match merge_into_channel_at(channel_id, &data, config, pool).await {
+                        // the survivor is polled at its own link, so nothing is left to store
+                        Ok(Some(survivor)) => {
+                            info!("Feed {rss_link} moved to {moved_to}, merged into {survivor}");
+                            return ChannelPollSummary {
+                                channel_id: survivor,
+                                rss_link,
+                                status: outcome.status,
+                                new_episodes,
+                                error: outcome.error,
+                            };
+                        }
+                        Ok(None) => warn!("Could not record move of {rss_link}: channel not found"),
+                        Err(err) => warn!("Could not merge {rss_link} into {moved_to}: {err}"),
+                    }
+                }
+                Err(err) => warn!("Could not record move of {rss_link}: {err}"),
+            }
+        }
+        match delta_update_feed(pool, &data, config.mark_removed_episodes).await {
+            Ok(count) => {
+                ensure_subscription(channel_id, data.websub.as_ref(), config, pool).await;
+                if let Err(err) = sync_artwork(&data, config, limiter, pool).await {
+                    warn!("Could not update artwork of {rss_link}: {err}");
+                }
+                new_episodes = count;
+                outcome.refresh_hint = data.refresh_hint;
+            }
+            Err(err) => {
+                outcome.status = FetchStatus::StoreError;
+                outcome.error = Some(format!("could not store episodes: {err}"));
+            }
+        }
+    }
+    if let Some(error) = &outcome.error {
+        warn!("Polling {rss_link} failed: {error}");
+    }
+
+    if let Err(err) = record_poll(channel_id, &rss_link, &outcome, redis_conn, pool).await {
+        warn!("Could not schedule next poll of {rss_link}: {err}");
+    }
+
+    ChannelPollSummary {
+        channel_id,
+        rss_link,
+        status: outcome.status,
+        new_episodes,
+        error: outcome.error,
+    }
+}
+
+struct PollOutcome {
+    status: FetchStatus,
+    error: Option<String>,
+    http_status: Option<i32>,
+    refresh_hint: Option<Duration>,
+}
+
+async fn record_poll(
+    channel_id: Uuid,
+    rss_link: &str,
+    outcome: &PollOutcome,
+    redis_conn: &mut redis::aio::ConnectionManager,
+    pool: &PgPool,
+) -> Result<()> {
+    let previous = sqlx::query!(
+        "SELECT failure_count, refresh_hint FROM channel_poll WHERE channel_id = $1",
+        channel_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let (failure_count, previous_hint) = previous
+        .map(|p| (p.failure_count, p.refresh_hint))
+        .unwrap_or_default();
+
+    // hints only come along with a full fetch, keep the last known one otherwise
+    let refresh_hint = outcome
+        .refresh_hint
+        .or_else(|| previous_hint.map(|secs| Duration::from_secs(secs as u64)));
+
+    let publish_dates = sqlx::query_scalar!(
+        "SELECT published FROM episode WHERE channel_id = $1 ORDER BY published DESC LIMIT $2",
+        channel_id,
+        CADENCE_SAMPLE_SIZE
+    )
+    .fetch_all(pool)
+    .await?;
+    let subscribers = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM user_subscriptions WHERE channel_id = $1"#,
+        channel_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let interval = poll_interval(&PollContext {
+        publish_dates,
+        refresh_hint,
+        cache_ttl: get_cache_ttl(redis_conn, rss_link).await,
+        subscribers,
+    });
+    let succeeded = !outcome.status.is_failure();
+    let (failure_count, interval) = if succeeded {
+        (0, interval)
+    } else {
+        let failure_count = failure_count + 1;
+        (
+            failure_count,
+            backoff_interval(interval, failure_count as u32),
+        )
+    };
+
+    let now = Utc::now();
+    let next_poll_at = now + chrono::Duration::from_std(interval)?;
+    let last_success_at = succeeded.then_some(now);
+    sqlx::query!(
+        r#"
+        INSERT INTO channel_poll(channel_id, next_poll_at, last_polled_at, last_success_at, failure_count, poll_interval, refresh_hint, last_status, last_error, last_http_status)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        ON CONFLICT (channel_id) DO UPDATE
+        SET next_poll_at = $2, last_polled_at = $3, last_success_at = COALESCE($4, channel_poll.last_success_at),
+            failure_count = $5, poll_interval = $6, refresh_hint = $7, last_status = $8, last_error = $9,
+            last_http_status = COALESCE($10, channel_poll.last_http_status)
+        "#,
+        channel_id,
+        next_poll_at,
+        now,
+        last_success_at,
+        failure_count,
+        interval.as_secs() as i32,
+        refresh_hint.map(|hint| hint.as_secs() as i32),
+        outcome.status.as_str(),
+        outcome.error,
+        outcome.http_status
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_channel_health(channel_id: Uuid, pool: &PgPool) -> Result<Option<ChannelHealth>> {
+    let health = sqlx::query_as!(
+        ChannelHealth,
+        r#"
+        SELECT last_status, last_error, failure_count as consecutive_failures, last_http_status,
+            last_polled_at, last_success_at, next_poll_at
+        FROM channel_poll WHERE channel_id = $1
+        "#,
+        channel_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(health)
+}
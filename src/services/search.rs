@@ -0,0 +1,197 @@
+// Full-text search over channels and episodes, backed by the indexes in the search migration
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::core::rss::{PodcastChannel, PodcastEpisodeDbResult};
+
+// Matched terms are delimited by control characters, which only become <mark> once the rest
+// of the snippet is escaped. Show notes have their markup stripped before this, but a broken
+// tag can survive that, so the snippet text itself is never trusted as HTML.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+const HEADLINE_OPTIONS: &str =
+    "StartSel=\u{2}, StopSel=\u{3}, MaxWords=30, MinWords=10, MaxFragments=2";
+
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+    pub subscribed_only: bool,
+    pub channel_id: Option<Uuid>,
+    /// Episodes published within [from, to)
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct ChannelHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub channel: PodcastChannel,
+    pub rank: f32,
+    pub snippet: Option<String>,
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct EpisodeHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub episode: PodcastEpisodeDbResult,
+    pub rank: f32,
+    pub snippet: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResults {
+    pub channels: Vec<ChannelHit>,
+    pub episodes: Vec<EpisodeHit>,
+}
+
+/// Ranked matches for a web-search style query (quoted phrases, `or`, `-excluded`)
+pub async fn search(
+    user_id: Uuid,
+    query: &str,
+    filter: &SearchFilter,
+    offset: i64,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<SearchResults> {
+    // channels have no publish date, so a date range narrows the results down to episodes
+    let channels = if filter.from.is_some() || filter.to.is_some() {
+        vec![]
+    } else {
+        search_channels(user_id, query, filter, offset, limit, pool).await?
+    };
+    let episodes = search_episodes(user_id, query, filter, offset, limit, pool).await?;
+    Ok(SearchResults { channels, episodes })
+}
+
+async fn search_channels(
+    user_id: Uuid,
+    query: &str,
+    filter: &SearchFilter,
+    offset: i64,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<ChannelHit>> {
+    let mut channels: Vec<ChannelHit> = sqlx::query_as(
+        r#"
+        SELECT c.*,
+            COALESCE((SELECT COUNT(episode.id) FROM episode WHERE episode.channel_id = c.id AND episode.removed_at IS NULL), 0) as num_episodes,
+            ts_headline('simple', translate(regexp_replace(COALESCE(c.description, c.title), '<[^>]*>', ' ', 'g'), E'\x02\x03', ''), query, $7) AS snippet
+        FROM (
+            SELECT channel.*, query, ts_rank_cd(channel_document(title, author, description), query) AS rank
+            FROM channel, websearch_to_tsquery('simple', $1) AS query
+            WHERE channel_document(title, author, description) @@ query
+            AND (NOT $2 OR id IN (SELECT channel_id FROM user_subscriptions WHERE user_id = $3))
+            AND ($4::uuid IS NULL OR id = $4)
+            ORDER BY rank DESC, title
+            OFFSET $5
+            LIMIT $6
+        ) AS c
+        ORDER BY rank DESC, title
+        "#,
+    )
+    .bind(query)
+    .bind(filter.subscribed_only)
+    .bind(user_id)
+    .bind(filter.channel_id)
+    .bind(offset)
+    .bind(limit)
+    .bind(HEADLINE_OPTIONS)
+    .fetch_all(pool)
+    .await?;
+    for channel in &mut channels {
+        channel.snippet = channel.snippet.as_deref().map(render_snippet);
+    }
+    Ok(channels)
+}
+
+async fn search_episodes(
+    user_id: Uuid,
+    query: &str,
+    filter: &SearchFilter,
+    offset: i64,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<EpisodeHit>> {
+    let mut episodes: Vec<EpisodeHit> = sqlx::query_as(
+        r#"
+        SELECT e.*, c.title as channel_title, c.image as channel_image, c.image_id as channel_image_id,
+            p.position, p.updated_at as position_updated_at,
+            ts_headline('simple', translate(regexp_replace(COALESCE(e.description, e.content, e.title), '<[^>]*>', ' ', 'g'), E'\x02\x03', ''), query, $9) AS snippet
+        FROM (
+            SELECT episode.*, query, ts_rank_cd(episode_document(title, tags, description, content), query) AS rank
+            FROM episode, websearch_to_tsquery('simple', $1) AS query
+            WHERE episode_document(title, tags, description, content) @@ query
+            AND removed_at IS NULL
+            AND (NOT $2 OR channel_id IN (SELECT channel_id FROM user_subscriptions WHERE user_id = $3))
+            AND ($4::uuid IS NULL OR channel_id = $4)
+            AND ($5::timestamptz IS NULL OR published >= $5)
+            AND ($6::timestamptz IS NULL OR published < $6)
+            ORDER BY rank DESC, published DESC
+            OFFSET $7
+            LIMIT $8
+        ) AS e
+        LEFT JOIN channel AS c ON c.id = e.channel_id
+        LEFT JOIN playback_position AS p ON p.episode_id = e.id AND p.user_id = $3
+        ORDER BY rank DESC, published DESC
+        "#,
+    )
+    .bind(query)
+    .bind(filter.subscribed_only)
+    .bind(user_id)
+    .bind(filter.channel_id)
+    .bind(filter.from)
+    .bind(filter.to)
+    .bind(offset)
+    .bind(limit)
+    .bind(HEADLINE_OPTIONS)
+    .fetch_all(pool)
+    .await?;
+    for episode in &mut episodes {
+        episode.snippet = episode.snippet.as_deref().map(render_snippet);
+    }
+    Ok(episodes)
+}
+
+/// HTML-escapes a headline and wraps its matches in <mark>
+fn render_snippet(headline: &str) -> String {
+    let mut html = String::with_capacity(headline.len());
+    for c in headline.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_snippet;
+
+    #[test]
+    fn marks_matches() {
+        assert_eq!(
+            render_snippet("about \u{2}rust\u{3} today"),
+            "about <mark>rust</mark> today"
+        );
+    }
+
+    #[test]
+    fn escapes_leftover_markup() {
+        assert_eq!(
+            render_snippet("<img src=x onerror=alert(1) \u{2}news\u{3} & \"more\""),
+            "&lt;img src=x onerror=alert(1) <mark>news</mark> &amp; &quot;more&quot;"
+        );
+    }
+}
@@ -0,0 +1,392 @@
+// Listening stats, built from the hourly totals position reports keep up to date.
+// Hours are bucketed in UTC, so in timezones with a half-hour offset they land in the
+// neighbouring local hour.
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// Channels and tags in the year in review
+const YEAR_TOP_COUNT: usize = 5;
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct ChannelHours {
+    pub channel_id: Uuid,
+    pub title: String,
+    pub hours: f64,
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct TagHours {
+    pub tag: String,
+    pub hours: f64,
+}
+
+/// Days in a row with any listening
+#[derive(Serialize, Debug, Default)]
+pub struct Streaks {
+    /// Ending today, or yesterday while today may still continue it
+    pub current: i64,
+    pub longest: i64,
+}
+
+/// History entries last listened to within the range, by state
+#[derive(Serialize, Debug, FromRow)]
+pub struct CompletionStats {
+    pub started: i64,
+    pub played: i64,
+    pub in_progress: i64,
+    pub abandoned: i64,
+    /// Share of started episodes that were played, from 0 to 1
+    pub rate: f64,
+    /// Average furthest point reached, in percent
+    pub average_completion: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListeningStats {
+    pub total_hours: f64,
+    pub channels: Vec<ChannelHours>,
+    /// An episode counts towards each of its tags, or its channel's when it has none
+    pub tags: Vec<TagHours>,
+    /// Hours by weekday, from Monday, and hour of the day
+    pub heatmap: Vec<[f64; 24]>,
+    pub streaks: Streaks,
+    pub completion: CompletionStats,
+}
+
+#[derive(Serialize, Debug)]
+pub struct YearInReview {
+    pub year: i32,
+    pub total_hours: f64,
+    pub episodes_played: i64,
+    pub top_channels: Vec<ChannelHours>,
+    pub top_tags: Vec<TagHours>,
+    pub longest_streak: i64,
+    /// Month with the most listening, from 1
+    pub busiest_month: Option<u32>,
+    pub busiest_day: Option<NaiveDate>,
+}
+
+/// The current date in the timezone
+pub async fn today(timezone: &str, pool: &PgPool) -> Result<NaiveDate> {
+    let today = sqlx::query_scalar!(
+        r#"SELECT (now() AT TIME ZONE $1)::date AS "today!""#,
+        timezone
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(today)
+}
+
+/// Stats of the listening between `start` and `end`, with days counted in the timezone
+pub async fn get_stats(
+    user_id: Uuid,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    timezone: &str,
+    pool: &PgPool,
+) -> Result<ListeningStats> {
+    let channels = get_channel_hours(user_id, (start, end), pool).await?;
+    let total_hours = channels.iter().map(|channel| channel.hours).sum();
+    let days = get_daily_hours(user_id, Some((start, end)), timezone, pool).await?;
+    // the current streak doesn't stop at the range
+    let all_days = get_daily_hours(user_id, None, timezone, pool).await?;
+    let today = today(timezone, pool).await?;
+    Ok(ListeningStats {
+        total_hours,
+        channels,
+        tags: get_tag_hours(user_id, (start, end), pool).await?,
+        heatmap: get_heatmap(user_id, (start, end), timezone, pool).await?,
+        streaks: Streaks {
+            current: current_streak(all_days.iter().map(|(day, _)| *day), today),
+            longest: longest_streak(days.iter().map(|(day, _)| *day)),
+        },
+        completion: get_completion(user_id, (start, end), pool).await?,
+    })
+}
+
+/// Summary of a calendar year, `range` being the year in the user's timezone
+pub async fn get_year_in_review(
+    user_id: Uuid,
+    year: i32,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    timezone: &str,
+    pool: &PgPool,
+) -> Result<YearInReview> {
+    let mut top_channels = get_channel_hours(user_id, (start, end), pool).await?;
+    let total_hours = top_channels.iter().map(|channel| channel.hours).sum();
+    top_channels.truncate(YEAR_TOP_COUNT);
+    let mut top_tags = get_tag_hours(user_id, (start, end), pool).await?;
+    top_tags.truncate(YEAR_TOP_COUNT);
+    let days = get_daily_hours(user_id, Some((start, end)), timezone, pool).await?;
+
+    let mut months = [0.0; 12];
+    for (day, hours) in &days {
+        months[day.month0() as usize] += hours;
+    }
+    let busiest_month = months
+        .iter()
+        .enumerate()
+        .filter(|(_, hours)| **hours > 0.0)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(month, _)| month as u32 + 1);
+    let busiest_day = days
+        .iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(day, _)| *day);
+
+    Ok(YearInReview {
+        year,
+        total_hours,
+        episodes_played: get_completion(user_id, (start, end), pool).await?.played,
+        top_channels,
+        top_tags,
+        longest_streak: longest_streak(days.iter().map(|(day, _)| *day)),
+        busiest_month,
+        busiest_day,
+    })
+}
+
+async fn get_channel_hours(
+    user_id: Uuid,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    pool: &PgPool,
+) -> Result<Vec<ChannelHours>> {
+    let channels = sqlx::query_as!(
+        ChannelHours,
+        r#"
+        SELECT c.id AS channel_id, c.title, SUM(lh.seconds) / 3600 AS "hours!"
+        FROM listening_hour AS lh
+        JOIN episode AS e ON e.id = lh.episode_id
+        JOIN channel AS c ON c.id = e.channel_id
+        WHERE lh.user_id = $1 AND lh.hour >= $2 AND lh.hour < $3
+        GROUP BY c.id
+        ORDER BY 3 DESC, c.title
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(channels)
+}
+
+async fn get_tag_hours(
+    user_id: Uuid,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    pool: &PgPool,
+) -> Result<Vec<TagHours>> {
+    let tags = sqlx::query_as!(
+        TagHours,
+        r#"
+        SELECT tag AS "tag!", SUM(lh.seconds) / 3600 AS "hours!"
+        FROM listening_hour AS lh
+        JOIN episode AS e ON e.id = lh.episode_id
+        JOIN channel AS c ON c.id = e.channel_id
+        CROSS JOIN LATERAL unnest(string_to_array(COALESCE(e.tags, c.tags), ',')) AS tags(raw_tag)
+        CROSS JOIN LATERAL (SELECT trim(raw_tag) AS tag) AS trimmed
+        WHERE lh.user_id = $1 AND lh.hour >= $2 AND lh.hour < $3 AND tag <> ''
+        GROUP BY tag
+        ORDER BY 2 DESC, tag
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(tags)
+}
+
+async fn get_heatmap(
+    user_id: Uuid,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    timezone: &str,
+    pool: &PgPool,
+) -> Result<Vec<[f64; 24]>> {
+    let cells = sqlx::query!(
+        r#"
+        SELECT EXTRACT(ISODOW FROM lh.hour AT TIME ZONE $4)::int AS "weekday!",
+            EXTRACT(HOUR FROM lh.hour AT TIME ZONE $4)::int AS "hour!",
+            SUM(lh.seconds) / 3600 AS "hours!"
+        FROM listening_hour AS lh
+        WHERE lh.user_id = $1 AND lh.hour >= $2 AND lh.hour < $3
+        GROUP BY 1, 2
+        "#,
+        user_id,
+        start,
+        end,
+        timezone
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut heatmap = vec![[0.0; 24]; 7];
+    for cell in cells {
+        heatmap[cell.weekday as usize - 1][cell.hour as usize] = cell.hours;
+    }
+    Ok(heatmap)
+}
+
+/// Hours per local day with any listening, oldest first, within the range when given
+async fn get_daily_hours(
+    user_id: Uuid,
+    range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    timezone: &str,
+    pool: &PgPool,
+) -> Result<Vec<(NaiveDate, f64)>> {
+    let (start, end) = range.unzip();
+    let days = sqlx::query!(
+        r#"
+        SELECT (lh.hour AT TIME ZONE $4)::date AS "day!", SUM(lh.seconds) / 3600 AS "hours!"
+        FROM listening_hour AS lh
+        WHERE lh.user_id = $1
+        AND ($2::timestamptz IS NULL OR lh.hour >= $2) AND ($3::timestamptz IS NULL OR lh.hour < $3)
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        user_id,
+        start,
+        end,
+        timezone
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.day, row.hours))
+    .collect();
+    Ok(days)
+}
+
+async fn get_completion(
+    user_id: Uuid,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    pool: &PgPool,
+) -> Result<CompletionStats> {
+    let completion = sqlx::query_as!(
+        CompletionStats,
+        r#"
+        SELECT COUNT(*) AS "started!",
+            COUNT(*) FILTER (WHERE state = 'played') AS "played!",
+            COUNT(*) FILTER (WHERE state = 'in_progress') AS "in_progress!",
+            COUNT(*) FILTER (WHERE state = 'abandoned') AS "abandoned!",
+            COALESCE(COUNT(*) FILTER (WHERE state = 'played')::float8 / NULLIF(COUNT(*), 0), 0) AS "rate!",
+            COALESCE(AVG(completion), 0) AS "average_completion!"
+        FROM user_watch_history
+        WHERE user_id = $1 AND last_listened_at >= $2 AND last_listened_at < $3
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(completion)
+}
+
+/// Longest run of consecutive days, the days given in ascending order
+fn longest_streak(days: impl Iterator<Item = NaiveDate>) -> i64 {
+    let (mut longest, mut current, mut previous) = (0, 0, None);
+    for day in days {
+        current = match previous {
+            Some(previous) if day - previous == Duration::days(1) => current + 1,
+            _ => 1,
+        };
+        longest = longest.max(current);
+        previous = Some(day);
+    }
+    longest
+}
+
+/// Run of consecutive days up to today or yesterday, the days given in ascending order
+fn current_streak(days: impl DoubleEndedIterator<Item = NaiveDate>, today: NaiveDate) -> i64 {
+    let mut expected = today;
+    let mut streak = 0;
+    for day in days.rev() {
+        if day > expected {
+            continue;
+        }
+        if day == expected {
+            streak += 1;
+        } else if streak == 0 && day == today - Duration::days(1) {
+            streak = 1;
+        } else {
+            break;
+        }
+        expected = day - Duration::days(1);
+    }
+    streak
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use super::{current_streak, longest_streak};
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 10, 22).unwrap()
+    }
+
+    /// Days relative to today, in ascending order
+    fn days(offsets: &[i64]) -> Vec<NaiveDate> {
+        offsets
+            .iter()
+            .map(|offset| today() + Duration::days(*offset))
+            .collect()
+    }
+
+    #[test]
+    fn longest_streak_picks_the_longest_run() {
+        assert_eq!(longest_streak(days(&[]).into_iter()), 0);
+        assert_eq!(longest_streak(days(&[-9]).into_iter()), 1);
+        assert_eq!(
+            longest_streak(days(&[-9, -8, -7, -5, -4, -1, 0]).into_iter()),
+            3
+        );
+    }
+
+    #[test]
+    fn longest_streak_spans_months() {
+        let days = [
+            NaiveDate::from_ymd_opt(2023, 2, 27).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 2, 28).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(),
+        ];
+        assert_eq!(longest_streak(days.into_iter()), 3);
+    }
+
+    #[test]
+    fn current_streak_ends_today() {
+        assert_eq!(current_streak(days(&[]).into_iter(), today()), 0);
+        assert_eq!(current_streak(days(&[0]).into_iter(), today()), 1);
+        assert_eq!(
+            current_streak(days(&[-5, -2, -1, 0]).into_iter(), today()),
+            3
+        );
+    }
+
+    #[test]
+    fn current_streak_ending_yesterday_still_counts() {
+        // today may still continue it
+        assert_eq!(current_streak(days(&[-1]).into_iter(), today()), 1);
+        assert_eq!(
+            current_streak(days(&[-4, -3, -2, -1]).into_iter(), today()),
+            4
+        );
+    }
+
+    #[test]
+    fn current_streak_is_broken_by_a_missed_day() {
+        assert_eq!(current_streak(days(&[-3, -2]).into_iter(), today()), 0);
+        assert_eq!(current_streak(days(&[-3, -1, 0]).into_iter(), today()), 2);
+    }
+
+    #[test]
+    fn current_streak_ignores_days_after_today() {
+        assert_eq!(current_streak(days(&[-1, 0, 1]).into_iter(), today()), 2);
+        assert_eq!(current_streak(days(&[-1, 2]).into_iter(), today()), 1);
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    core::{
+        rss::{RssData, WebSubLinks},
+        websub::{callback_url, generate_secret, request_subscription, WebSubSubscription},
+    },
+};
+
+use super::{channel::get_channel, feed::delta_update_feed};
+
+pub async fn get_subscription(
+    channel_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<WebSubSubscription>> {
+    let subscription = sqlx::query_as!(
+        WebSubSubscription,
+        "SELECT channel_id, hub, topic, secret, verified, denied FROM websub_subscription WHERE channel_id = $1",
+        channel_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscription)
+}
+
+/// Subscribes to the channel's hub if it advertises one and we aren't already subscribed there,
+/// or it denied us. Failures are only logged since polling still covers the channel.
+pub async fn ensure_subscription(
+    channel_id: Uuid,
+    links: Option<&WebSubLinks>,
+    config: &Config,
+    pool: &PgPool,
+) {
+    let Some(links) = links else {
+        return;
+    };
+    let result = match get_subscription(channel_id, pool).await {
+        Ok(Some(existing)) if existing.hub == links.hub && existing.topic == links.topic => Ok(()),
+        Ok(_) => subscribe(channel_id, links, config, pool).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        warn!("WebSub subscription for channel {channel_id} failed: {err}");
+    }
+}
+
+pub async fn subscribe(
+    channel_id: Uuid,
+    links: &WebSubLinks,
+    config: &Config,
+    pool: &PgPool,
+) -> Result<()> {
+    // the row has to exist before the hub calls back to verify our intent
+    let subscription = sqlx::query_as!(
+        WebSubSubscription,
+        r#"
+        INSERT INTO websub_subscription(channel_id, hub, topic, secret)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (channel_id) DO UPDATE
+        SET hub = $2, topic = $3, secret = $4, verified = false, denied = false,
+            lease_expires_at = NULL, updated_at = now()
+        RETURNING channel_id, hub, topic, secret, verified, denied
+        "#,
+        channel_id,
+        links.hub,
+        links.topic,
+        generate_secret()
+    )
+    .fetch_one(pool)
+    .await?;
+
+    info!(
+        "Subscribing to WebSub hub {} for channel {channel_id}",
+        links.hub
+    );
+    request_subscription(&subscription, &callback_url(&config.public_url, channel_id)).await
+}
+
+pub async fn confirm_subscription(
+    channel_id: Uuid,
+    lease_seconds: i64,
+    pool: &PgPool,
+) -> Result<bool> {
+    let lease_expires_at = Utc::now() + Duration::seconds(lease_seconds);
+    let rows_affected = sqlx::query!(
+        r#"
+        UPDATE websub_subscription
+        SET verified = true, denied = false, lease_expires_at = $2, updated_at = now()
+        WHERE channel_id = $1
+        "#,
+        channel_id,
+        lease_expires_at
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(rows_affected > 0)
+}
+
+/// Keeps the refused subscription around so it isn't requested again from the same hub
+pub async fn deny_subscription(channel_id: Uuid, pool: &PgPool) -> Result<bool> {
+    let rows_affected = sqlx::query!(
+        r#"
+        UPDATE websub_subscription
+        SET verified = false, denied = true, lease_expires_at = NULL, updated_at = now()
+        WHERE channel_id = $1
+        "#,
+        channel_id
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(rows_affected > 0)
+}
+
+/// Asks the hub to extend the subscription with the secret it already has. The current lease
+/// stays valid meanwhile, so pushes keep being accepted until the hub verifies the renewal.
+pub async fn renew_subscription(
+    subscription: &WebSubSubscription,
+    config: &Config,
+    pool: &PgPool,
+) -> Result<()> {
+    // tracks the attempt, so unverified requests aren't retried on every run
+    sqlx::query!(
+        "UPDATE websub_subscription SET updated_at = now() WHERE channel_id = $1",
+        subscription.channel_id
+    )
+    .execute(pool)
+    .await?;
+
+    info!(
+        "Renewing WebSub subscription at {} for channel {}",
+        subscription.hub, subscription.channel_id
+    );
+    request_subscription(
+        subscription,
+        &callback_url(&config.public_url, subscription.channel_id),
+    )
+    .await
+}
+
+/// Renews leases that are about to run out, and retries requests the hub never verified.
+/// Subscriptions the hub denied are left alone.
+pub async fn renew_expiring_subscriptions(config: &Config, pool: &PgPool) -> Result<()> {
+    let renew_before = Utc::now() + Duration::days(1);
+    let retry_unverified_after = Utc::now() - Duration::days(1);
+    let subscriptions = sqlx::query_as!(
+        WebSubSubscription,
+        r#"
+        SELECT channel_id, hub, topic, secret, verified, denied FROM websub_subscription
+        WHERE (verified AND lease_expires_at < $1)
+        OR (NOT verified AND NOT denied AND updated_at < $2)
+        "#,
+        renew_before,
+        retry_unverified_after
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for subscription in subscriptions {
+        if let Err(err) = renew_subscription(&subscription, config, pool).await {
+            warn!(
+                "Could not renew WebSub lease for channel {}: {err}",
+                subscription.channel_id
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Applies a content distribution pushed by the hub, returning the feed it carried
+pub async fn apply_push(channel_id: Uuid, body: &[u8], pool: &PgPool) -> Result<RssData> {
+    let channel = get_channel(channel_id, pool)
+        .await?
+        .ok_or(anyhow!("channel not found"))?;
+    let feed = feed_rs::parser::parse(body)?;
+    let mut data =
+        RssData::from_feed(&feed, body, channel.rss_link).ok_or(anyhow!("could not parse feed"))?;
+
+    // pushed payloads may be partial, so trust the subscription rather than the payload for identity
+    // and don't take missing episodes as removed
+    data.bind_to_channel(channel_id);
+
+    delta_update_feed(pool, &data, false).await?;
+    Ok(data)
+}